@@ -0,0 +1,171 @@
+use csv::WriterBuilder;
+use log::{info, warn};
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+use crate::{DynResult, OutputRecord};
+
+// sqlite archive of all imported records, keyed by source and transaction number
+pub struct Archive {
+    conn: Connection,
+}
+
+impl Archive {
+    pub fn open(db_file: &Path) -> DynResult<Archive> {
+        info!("打开归档数据库: {}", db_file.display());
+        let conn = Connection::open(db_file)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS runs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                started_at TEXT NOT NULL,
+                user TEXT NOT NULL,
+                inputs TEXT NOT NULL,
+                output TEXT NOT NULL,
+                record_count INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS records (
+                source TEXT NOT NULL,
+                transaction_id TEXT NOT NULL,
+                date TEXT NOT NULL,
+                type TEXT NOT NULL,
+                amount REAL NOT NULL,
+                category1 TEXT NOT NULL,
+                category2 TEXT NOT NULL,
+                account1 TEXT NOT NULL,
+                account2 TEXT NOT NULL,
+                remark TEXT NOT NULL,
+                currency TEXT NOT NULL,
+                tag TEXT NOT NULL,
+                run_id INTEGER NOT NULL REFERENCES runs(id),
+                PRIMARY KEY (source, transaction_id)
+            );
+            CREATE TABLE IF NOT EXISTS raw_rows (
+                source TEXT NOT NULL,
+                transaction_id TEXT NOT NULL,
+                row TEXT NOT NULL,
+                run_id INTEGER NOT NULL REFERENCES runs(id),
+                PRIMARY KEY (source, transaction_id)
+            );
+            CREATE TABLE IF NOT EXISTS rule_hits (
+                run_id INTEGER NOT NULL REFERENCES runs(id),
+                source TEXT NOT NULL,
+                transaction_id TEXT NOT NULL,
+                rule TEXT NOT NULL
+            );",
+        )?;
+        Ok(Archive { conn })
+    }
+
+    // upsert records of this run, returns the number of archived records
+    pub fn save(
+        &mut self,
+        user: &str,
+        inputs: &[&Path],
+        output_file: &Path,
+        records: &[OutputRecord],
+    ) -> DynResult<usize> {
+        let tx = self.conn.transaction()?;
+
+        let inputs = inputs
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect::<Vec<_>>()
+            .join(";");
+        tx.execute(
+            "INSERT INTO runs (started_at, user, inputs, output, record_count)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                chrono::Local::now().to_rfc3339(),
+                user,
+                inputs,
+                output_file.display().to_string(),
+                records.len() as i64,
+            ],
+        )?;
+        let run_id = tx.last_insert_rowid();
+
+        let mut count = 0;
+        for record in records {
+            if record.transaction_id.is_empty() {
+                warn!("{} {}: 缺少交易单号，跳过归档", record.date, record.source);
+                continue;
+            }
+
+            tx.execute(
+                "INSERT INTO records (source, transaction_id, date, type, amount, category1,
+                     category2, account1, account2, remark, currency, tag, run_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+                 ON CONFLICT (source, transaction_id) DO UPDATE SET
+                     date = excluded.date,
+                     type = excluded.type,
+                     amount = excluded.amount,
+                     category1 = excluded.category1,
+                     category2 = excluded.category2,
+                     account1 = excluded.account1,
+                     account2 = excluded.account2,
+                     remark = excluded.remark,
+                     currency = excluded.currency,
+                     tag = excluded.tag,
+                     run_id = excluded.run_id",
+                params![
+                    record.source,
+                    record.transaction_id,
+                    record.date_time().to_rfc3339(),
+                    record.r#type,
+                    record.amount as f64,
+                    record.category1,
+                    record.category2,
+                    record.account1,
+                    record.account2,
+                    record.remark,
+                    record.currency,
+                    record.tag,
+                    run_id,
+                ],
+            )?;
+
+            tx.execute(
+                "INSERT INTO raw_rows (source, transaction_id, row, run_id)
+                 VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (source, transaction_id) DO UPDATE SET
+                     row = excluded.row,
+                     run_id = excluded.run_id",
+                params![
+                    record.source,
+                    record.transaction_id,
+                    raw_to_csv_line(&record.raw)?,
+                    run_id,
+                ],
+            )?;
+
+            // rule hits describe the latest categorization only
+            tx.execute(
+                "DELETE FROM rule_hits WHERE source = ?1 AND transaction_id = ?2",
+                params![record.source, record.transaction_id],
+            )?;
+            for rule in &record.rule_hits {
+                tx.execute(
+                    "INSERT INTO rule_hits (run_id, source, transaction_id, rule)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![run_id, record.source, record.transaction_id, rule],
+                )?;
+            }
+
+            count += 1;
+        }
+
+        tx.commit()?;
+        Ok(count)
+    }
+}
+
+// keep the raw row as a single csv line, so it can be parsed again later
+fn raw_to_csv_line(raw: &[String]) -> DynResult<String> {
+    let mut wtr = WriterBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_writer(Vec::new());
+    wtr.write_record(raw)?;
+    let line = String::from_utf8(wtr.into_inner()?)?;
+    Ok(line.trim_end().to_string())
+}
@@ -1,6 +1,9 @@
+use chrono::{Datelike, Days, Local, Months, NaiveDate};
 use clap::{self, Parser};
-use std::path::PathBuf;
+use encoding_rs::Encoding;
+use std::path::{Path, PathBuf};
 
+use crate::encoding::parse_encoding_label;
 use crate::DynResult;
 
 #[derive(Parser, Debug)]
@@ -12,11 +15,99 @@ pub struct Args {
     #[clap(short = 'w', long = "wx-bill", value_parser=clap::value_parser!(PathBuf))]
     pub weixin_bill: Option<PathBuf>,
 
+    #[clap(short = 'j', long = "jd-bill", value_parser=clap::value_parser!(PathBuf))]
+    pub jingdong_bill: Option<PathBuf>,
+
+    #[clap(short = 'y', long = "ysf-bill", value_parser=clap::value_parser!(PathBuf))]
+    pub yunshanfu_bill: Option<PathBuf>,
+
+    #[clap(short = 'c', long = "cmb-bill", value_parser=clap::value_parser!(PathBuf))]
+    pub zhaoshang_bill: Option<PathBuf>,
+
+    #[clap(long = "paypal-bill", value_parser=clap::value_parser!(PathBuf))]
+    pub paypal_bill: Option<PathBuf>,
+
+    // iCost export of the existing books
+    #[clap(long = "icost-history", value_parser=clap::value_parser!(PathBuf))]
+    pub icost_history: Option<PathBuf>,
+
+    // write the history records into the output as well
+    #[clap(long = "merge-history", requires = "icost_history")]
+    pub merge_history: bool,
+
+    #[clap(long = "meituan-orders", value_parser=clap::value_parser!(PathBuf))]
+    pub meituan_orders: Option<PathBuf>,
+
+    #[clap(long = "pdd-orders", value_parser=clap::value_parser!(PathBuf))]
+    pub pinduoduo_orders: Option<PathBuf>,
+
+    // <definition.toml>=<bill.csv>, can be given multiple times
+    #[clap(long = "custom-bill", value_parser=parse_custom_bill)]
+    pub custom_bills: Vec<CustomBill>,
+
+    // csv of date, currency and rate to convert foreign currency records to CNY
+    #[clap(long = "rates", value_parser=clap::value_parser!(PathBuf))]
+    pub rates: Option<PathBuf>,
+
+    // <source>=<label> or <bill>=<label>, e.g. zfb=gbk, can be given multiple times
+    #[clap(long = "encoding", value_parser=parse_encoding_override)]
+    pub encodings: Vec<EncodingOverride>,
+
+    // csv of account, date and balance taken from statements to check the records against
+    #[clap(long = "reconcile", value_parser=clap::value_parser!(PathBuf))]
+    pub reconcile: Option<PathBuf>,
+
+    // csv of bank, card tail and account to resolve credit card repayment targets
+    #[clap(long = "cards", value_parser=clap::value_parser!(PathBuf))]
+    pub cards: Option<PathBuf>,
+
+    // csv of counterparty name and account of household members, red packets
+    // between them are booked as transfers
+    #[clap(long = "family", value_parser=clap::value_parser!(PathBuf))]
+    pub family: Option<PathBuf>,
+
+    // fold 群收款 shares paid back by friends into the group expense
+    #[clap(long = "link-aa")]
+    pub link_aa: bool,
+
+    // regex of counterparty or remark of expenses to be reimbursed, can be given multiple times
+    #[clap(long = "reimbursable")]
+    pub reimbursable: Vec<String>,
+
+    // csv keeping the outstanding reimbursements between runs
+    #[clap(long = "reimburse-state", value_parser=clap::value_parser!(PathBuf))]
+    pub reimburse_state: Option<PathBuf>,
+
     #[clap(short='o', long="output", value_parser=clap::value_parser!(PathBuf))]
     pub output: Option<PathBuf>,
 
     #[clap(short='u', long="user", value_parser=parse_user)]
     pub user: User,
+
+    #[clap(long = "db", value_parser=clap::value_parser!(PathBuf))]
+    pub database: Option<PathBuf>,
+
+    #[clap(short = 'p', long = "profile", value_parser=parse_profile, default_value = "icost")]
+    pub profile: Profile,
+
+    #[clap(short = 't', long = "template", value_parser=clap::value_parser!(PathBuf))]
+    pub template: Option<PathBuf>,
+
+    #[clap(long = "split-by", value_parser=parse_split_by)]
+    pub split_by: Option<SplitBy>,
+
+    // fold daily fund yields of an account into one record per week or month
+    #[clap(long = "aggregate-yield", value_parser=parse_period)]
+    pub aggregate_yield: Option<Period>,
+
+    #[clap(long = "since", value_parser=parse_date_spec)]
+    pub since: Option<DateSpec>,
+
+    #[clap(long = "until", value_parser=parse_date_spec)]
+    pub until: Option<DateSpec>,
+
+    #[clap(long = "ascending")]
+    pub ascending: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -25,6 +116,15 @@ pub enum User {
     Han,
 }
 
+impl User {
+    pub fn name(&self) -> &'static str {
+        match self {
+            User::Yang => "yang",
+            User::Han => "han",
+        }
+    }
+}
+
 pub fn parse_user(user: &str) -> DynResult<User> {
     match user {
         "yang" => Ok(User::Yang),
@@ -32,3 +132,166 @@ pub fn parse_user(user: &str) -> DynResult<User> {
         _ => Err("Invalid user".into()),
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct CustomBill {
+    pub definition: PathBuf,
+    pub bill: PathBuf,
+}
+
+pub fn parse_custom_bill(custom_bill: &str) -> DynResult<CustomBill> {
+    match custom_bill.split_once('=') {
+        Some((definition, bill)) => Ok(CustomBill {
+            definition: PathBuf::from(definition),
+            bill: PathBuf::from(bill),
+        }),
+        None => Err("Invalid custom bill, use <definition.toml>=<bill.csv>".into()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EncodingOverride {
+    // source name like "zfb" or the path of a bill
+    pub input: String,
+    pub encoding: &'static Encoding,
+}
+
+pub fn parse_encoding_override(encoding_override: &str) -> DynResult<EncodingOverride> {
+    match encoding_override.split_once('=') {
+        Some((input, label)) => Ok(EncodingOverride {
+            input: input.to_string(),
+            encoding: parse_encoding_label(label)?,
+        }),
+        None => Err("Invalid encoding, use <source>=<label> or <bill>=<label>".into()),
+    }
+}
+
+impl Args {
+    // encoding given for the source or the bill file, detected if none
+    pub fn encoding(&self, source: &str, input_file: &Path) -> Option<&'static Encoding> {
+        self.encodings
+            .iter()
+            .rev()
+            .find(|o| o.input == source || Path::new(&o.input) == input_file)
+            .map(|o| o.encoding)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Profile {
+    ICost,
+    Firefly,
+    Actual,
+}
+
+pub fn parse_profile(profile: &str) -> DynResult<Profile> {
+    match profile {
+        "icost" => Ok(Profile::ICost),
+        "firefly" => Ok(Profile::Firefly),
+        "actual" => Ok(Profile::Actual),
+        _ => Err("Invalid profile".into()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SplitBy {
+    Month,
+    User,
+    Account,
+    Source,
+}
+
+pub fn parse_split_by(split_by: &str) -> DynResult<SplitBy> {
+    match split_by {
+        "month" => Ok(SplitBy::Month),
+        "user" => Ok(SplitBy::User),
+        "account" => Ok(SplitBy::Account),
+        "source" => Ok(SplitBy::Source),
+        _ => Err("Invalid split-by".into()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Period {
+    Week,
+    Month,
+}
+
+impl Period {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Period::Week => "week",
+            Period::Month => "month",
+        }
+    }
+
+    // first day of the period the date falls in, weeks start on monday
+    pub fn first_day(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Week => date - Days::new(date.weekday().num_days_from_monday() as u64),
+            Period::Month => first_day_of_month(date),
+        }
+    }
+
+    // last day of the period the date falls in
+    pub fn last_day(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Week => self.first_day(date) + Days::new(6),
+            Period::Month => (first_day_of_month(date) + Months::new(1))
+                .pred_opt()
+                .unwrap(),
+        }
+    }
+}
+
+pub fn parse_period(period: &str) -> DynResult<Period> {
+    match period {
+        "week" => Ok(Period::Week),
+        "month" => Ok(Period::Month),
+        _ => Err("Invalid period, use week or month".into()),
+    }
+}
+
+// date given on command line, either a fixed date or a month relative to today
+#[derive(Debug, Clone)]
+pub enum DateSpec {
+    Date(NaiveDate),
+    ThisMonth,
+    LastMonth,
+}
+
+impl DateSpec {
+    // first day covered by the date, used by --since
+    pub fn first_day(&self) -> NaiveDate {
+        match self {
+            DateSpec::Date(date) => *date,
+            DateSpec::ThisMonth => first_day_of_month(Local::now().date_naive()),
+            DateSpec::LastMonth => first_day_of_month(Local::now().date_naive()) - Months::new(1),
+        }
+    }
+
+    // last day covered by the date, used by --until
+    pub fn last_day(&self) -> NaiveDate {
+        match self {
+            DateSpec::Date(date) => *date,
+            DateSpec::ThisMonth => Local::now().date_naive(),
+            DateSpec::LastMonth => first_day_of_month(Local::now().date_naive())
+                .pred_opt()
+                .unwrap(),
+        }
+    }
+}
+
+fn first_day_of_month(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap()
+}
+
+pub fn parse_date_spec(date: &str) -> DynResult<DateSpec> {
+    match date {
+        "this-month" => Ok(DateSpec::ThisMonth),
+        "last-month" => Ok(DateSpec::LastMonth),
+        _ => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map(DateSpec::Date)
+            .map_err(|_| "Invalid date, use YYYY-MM-DD, this-month or last-month".into()),
+    }
+}
@@ -0,0 +1,132 @@
+use log::{info, warn};
+use regex::Regex;
+use std::path::Path;
+
+use crate::encoding::open_csv_with_headers;
+use crate::{DynResult, OutputRecord};
+
+// header names used by the cards file, the first found is used
+const BANK_HEADERS: [&str; 2] = ["银行", "bank"];
+const TAIL_HEADERS: [&str; 2] = ["尾号", "tail"];
+const ACCOUNT_HEADERS: [&str; 2] = ["账户", "account"];
+
+// credit card configured by the user, e.g. "招商银行,1234,招行信用卡-杨"
+#[derive(Debug)]
+struct Card {
+    bank: String,
+    tail: String,
+    account: String,
+}
+
+// resolves the target card of a repayment from the bank name and the card
+// tail found in its counterparty and remark
+pub struct CardAccounts {
+    cards: Vec<Card>,
+    tail_re: Regex,
+    bank_re: Regex,
+}
+
+impl CardAccounts {
+    pub fn new() -> CardAccounts {
+        CardAccounts {
+            cards: Vec::new(),
+            // "尾号1234", "(1234)" or "1234" at the end
+            tail_re: Regex::new(r"(?:尾号\s*(\d{4}))|(?:[(（](\d{4})[)）])|(?:(\d{4})$)").unwrap(),
+            // bank name followed by the card, not "转出到银行卡"
+            bank_re: Regex::new(r"(\p{Han}{2,8}?银行)(?:信用卡|储蓄卡|借记卡|尾号|[(（]|\s|$)")
+                .unwrap(),
+        }
+    }
+
+    // read a csv of bank, card tail and account
+    pub fn load(input_file: &Path) -> DynResult<CardAccounts> {
+        let (mut rdr, find) = open_csv_with_headers(input_file, "信用卡文件")?;
+        let bank_index = find(&BANK_HEADERS)?;
+        let tail_index = find(&TAIL_HEADERS)?;
+        let account_index = find(&ACCOUNT_HEADERS)?;
+
+        let mut card_accounts = CardAccounts::new();
+        for result in rdr.records() {
+            let record = result?;
+            let get = |index: usize| record.get(index).unwrap_or("").to_string();
+            card_accounts.cards.push(Card {
+                bank: get(bank_index),
+                tail: get(tail_index),
+                account: get(account_index),
+            });
+        }
+        info!("读取信用卡账户: {} 张", card_accounts.cards.len());
+
+        Ok(card_accounts)
+    }
+
+    // account of the card named in the text, a configured card if the tail and
+    // the bank match, otherwise named like the bank statements "招商银行信用卡(1234)"
+    // when the kind of the card is known
+    fn resolve(&self, text: &str) -> Option<String> {
+        let tail = self.tail_re.captures(text).and_then(|c| {
+            c.iter()
+                .skip(1)
+                .flatten()
+                .next()
+                .map(|m| m.as_str().to_string())
+        });
+        let bank = self.bank_re.captures(text).map(|c| c[1].to_string());
+
+        if tail.is_none() && bank.is_none() {
+            return None;
+        }
+
+        // the only configured card with the tail, or of the bank if there is no tail
+        let candidates: Vec<&Card> = self
+            .cards
+            .iter()
+            .filter(|card| tail.as_ref().is_none_or(|tail| card.tail == *tail))
+            .filter(|card| {
+                if tail.is_some() {
+                    bank.is_none() || card.bank.is_empty() || text.contains(&card.bank)
+                } else {
+                    !card.bank.is_empty() && text.contains(&card.bank)
+                }
+            })
+            .collect();
+        match candidates.as_slice() {
+            [card] => Some(card.account.clone()),
+            [] => {
+                let kind = if text.contains("信用卡") {
+                    "信用卡"
+                } else if text.contains("储蓄卡") || text.contains("借记卡") {
+                    "储蓄卡"
+                } else {
+                    return None;
+                };
+                Some(format!("{}{}({})", bank?, kind, tail?))
+            }
+            _ => None,
+        }
+    }
+
+    // fill the unknown target card of repayments and withdrawals, returns the
+    // number of filled records
+    pub fn apply(&self, records: &mut [OutputRecord]) -> usize {
+        let mut count = 0;
+        for record in records.iter_mut() {
+            if record.r#type != "转账" || record.account2 != "未知" {
+                continue;
+            }
+            let text = format!("{} {}", record.counterparty, record.remark);
+            match self.resolve(&text) {
+                Some(account) => {
+                    record.rule_hits.push(format!("还款目标卡:{}", account));
+                    record.account2 = account;
+                    count += 1;
+                }
+                None => warn!(
+                    "{} {}: 没有找到转账目标卡: {}",
+                    record.date, record.source, text
+                ),
+            }
+        }
+        count
+    }
+}
@@ -0,0 +1,130 @@
+// category rules shared by all bill sources
+// counterparty and remark are matched against known shops and goods, the last
+// matching rule wins, records without any matching rule fall into "未知"
+pub fn filter_category(
+    counterparty: &str,
+    remark: &str,
+    transaction_type: &str,
+    amount: f32,
+    rule_hits: &mut Vec<String>,
+) -> (String, String) {
+    if transaction_type == "转账" {
+        return ("".to_string(), "".to_string());
+    }
+
+    let mut category1 = "未知".to_string();
+    let mut category2 = "".to_string();
+    match counterparty {
+        "北京一卡通" => {
+            category1 = "交通".to_string();
+            if amount < 2.0 {
+                category2 = "公交".to_string();
+            } else {
+                category2 = "地铁".to_string();
+            }
+        }
+        "饿了么" => {
+            category1 = "餐饮".to_string();
+            category2 = "外卖".to_string();
+        }
+        "兴全基金管理有限公司" => {
+            if transaction_type == "收入" {
+                category1 = "资本".to_string();
+                category2 = "投资收入".to_string();
+            } else if transaction_type == "支出" {
+                category1 = "资本".to_string();
+                category2 = "投资亏损".to_string();
+            }
+        }
+        "中国移动" if remark.contains("话费充值") => {
+            category1 = "账单".to_string();
+            category2 = "电话费".to_string();
+        }
+        "蚂蚁森林" => {
+            category1 = "意外收入".to_string();
+        }
+        "Steam" => {
+            category1 = "网络".to_string();
+            category2 = "游戏".to_string();
+        }
+        "众博康健大药房" => {
+            category1 = "医疗".to_string();
+            category2 = "药品".to_string();
+        }
+        "北京永辉超市有限公司" => {
+            category1 = "食材".to_string();
+            category2 = "蔬菜".to_string();
+        }
+        "北京大学口腔医院" => {
+            category1 = "医疗".to_string();
+            category2 = "牙齿".to_string();
+        }
+        "淮南牛肉汤" => {
+            category1 = "餐饮".to_string();
+            category2 = "三餐".to_string();
+        }
+        "汤鲜生浦项中心店" => {
+            category1 = "餐饮".to_string();
+            category2 = "三餐".to_string();
+        }
+        "滴滴出行（北京）网络平台技术有限公司" => {
+            category1 = "交通".to_string();
+            category2 = "打车".to_string();
+        }
+        _ => {}
+    }
+    if category1 != "未知" {
+        rule_hits.push(format!("交易对方:{}", counterparty));
+    }
+
+    match remark {
+        "电费" => {
+            category1 = "账单".to_string();
+            category2 = "电费".to_string();
+        }
+        "火车票" => {
+            category1 = "交通".to_string();
+            category2 = "火车".to_string();
+        }
+        _ => {}
+    }
+    if remark == "电费" || remark == "火车票" {
+        rule_hits.push(format!("商品说明:{}", remark));
+    }
+
+    // counterparty names contain the shop name
+    if counterparty.contains("禹泉水处理设备") {
+        category1 = "账单".to_string();
+        category2 = "水费".to_string();
+        rule_hits.push("交易对方:禹泉水处理设备".to_string());
+    } else if counterparty.contains("北京市顺义区妇幼保健院") {
+        category1 = "医疗".to_string();
+        category2 = "门诊".to_string();
+        rule_hits.push("交易对方:北京市顺义区妇幼保健院".to_string());
+    } else if counterparty.contains("易寄件") {
+        category1 = "杂项".to_string();
+        category2 = "快递费".to_string();
+        rule_hits.push("交易对方:易寄件".to_string());
+    } else if counterparty.contains("顺义鑫绿都生活超市后沙峪店")
+        || counterparty.contains("永辉超市")
+    {
+        category1 = "食材".to_string();
+        category2 = "蔬菜".to_string();
+        rule_hits.push("交易对方:超市".to_string());
+    }
+
+    if remark.contains("霸王茶姬") {
+        category1 = "餐饮".to_string();
+        category2 = "饮料".to_string();
+        rule_hits.push("商品说明:霸王茶姬".to_string());
+    }
+
+    // yields of money-market funds, e.g. "余额宝-2024.05.05-收益发放" or "零钱通收益"
+    if transaction_type == "收入" && remark.contains("收益") {
+        category1 = "资本".to_string();
+        category2 = "投资收入".to_string();
+        rule_hits.push("商品说明:收益".to_string());
+    }
+
+    (category1, category2)
+}
@@ -0,0 +1,132 @@
+use chrono::NaiveDate;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::datetime::parse_date_time;
+use crate::encoding::open_csv_with_headers;
+use crate::{DynResult, OutputRecord};
+
+// currency markers found in amount columns, longer markers first so that
+// "US$" is not taken as "$" and "JP¥" is not taken as "¥"
+const CURRENCY_MARKERS: [(&str, &str); 19] = [
+    ("US$", "USD"),
+    ("HK$", "HKD"),
+    ("NT$", "TWD"),
+    ("JP¥", "JPY"),
+    ("CNY", "CNY"),
+    ("RMB", "CNY"),
+    ("USD", "USD"),
+    ("JPY", "JPY"),
+    ("EUR", "EUR"),
+    ("GBP", "GBP"),
+    ("HKD", "HKD"),
+    ("円", "JPY"),
+    ("¥", "CNY"),
+    ("￥", "CNY"),
+    ("元", "CNY"),
+    ("$", "USD"),
+    ("€", "EUR"),
+    ("£", "GBP"),
+    ("₩", "KRW"),
+];
+
+// detect ISO 4217 currency code from an amount string like "US$12.00" or "JPY 1,200"
+pub fn detect_currency(amount: &str) -> Option<&'static str> {
+    CURRENCY_MARKERS
+        .iter()
+        .find(|(marker, _)| amount.contains(marker))
+        .map(|(_, code)| *code)
+}
+
+// normalize a currency column value, e.g. "usd" or "美元", to ISO 4217 code
+pub fn normalize_currency(currency: &str) -> Option<&'static str> {
+    let currency = currency.trim();
+    match currency {
+        "人民币" => Some("CNY"),
+        "美元" => Some("USD"),
+        "日元" => Some("JPY"),
+        "欧元" => Some("EUR"),
+        "英镑" => Some("GBP"),
+        "港币" | "港元" => Some("HKD"),
+        _ => detect_currency(&currency.to_uppercase()),
+    }
+}
+
+// CNY per unit of foreign currency, by currency and effective date
+pub struct RateTable {
+    rates: HashMap<String, BTreeMap<NaiveDate, f32>>,
+}
+
+// header names used by the rates file, the first found is used
+const DATE_HEADERS: [&str; 2] = ["日期", "date"];
+const CURRENCY_HEADERS: [&str; 2] = ["货币", "currency"];
+const RATE_HEADERS: [&str; 2] = ["汇率", "rate"];
+
+impl RateTable {
+    // read a csv of date, currency and rate, e.g. "2024-03-01,USD,7.19"
+    pub fn load(input_file: &Path) -> DynResult<RateTable> {
+        let (mut rdr, find) = open_csv_with_headers(input_file, "汇率文件")?;
+        let date_index = find(&DATE_HEADERS)?;
+        let currency_index = find(&CURRENCY_HEADERS)?;
+        let rate_index = find(&RATE_HEADERS)?;
+
+        let mut rates: HashMap<String, BTreeMap<NaiveDate, f32>> = HashMap::new();
+        for result in rdr.records() {
+            let record = result?;
+            let get = |index: usize| record.get(index).unwrap_or("");
+            let date_str = get(date_index);
+            let date = parse_date_time(date_str)
+                .map_err(|e| format!("{} 汇率: {}", date_str, e))?
+                .date();
+            let currency = normalize_currency(get(currency_index))
+                .ok_or_else(|| format!("{} 汇率: 未知的货币: {}", date_str, get(currency_index)))?;
+            let rate = get(rate_index)
+                .parse::<f32>()
+                .map_err(|e| format!("{} 汇率: 不支持的汇率格式: {}", date_str, e))?;
+            rates
+                .entry(currency.to_string())
+                .or_default()
+                .insert(date, rate);
+        }
+
+        Ok(RateTable { rates })
+    }
+
+    // latest rate published on or before the date
+    fn rate(&self, currency: &str, date: NaiveDate) -> Option<f32> {
+        self.rates
+            .get(currency)?
+            .range(..=date)
+            .next_back()
+            .map(|(_, rate)| *rate)
+    }
+
+    // convert foreign currency records to CNY, keeping the original amount in
+    // the remark, returns the number of converted records
+    pub fn convert(&self, records: &mut [OutputRecord]) -> usize {
+        let mut count = 0;
+        for record in records.iter_mut() {
+            if record.currency.is_empty() || record.currency == "CNY" {
+                continue;
+            }
+            let Some(rate) = self.rate(&record.currency, record.date.date()) else {
+                println!(
+                    "{} {}: 没有{}的汇率，保留原币种: {}",
+                    record.date, record.source, record.currency, record.remark
+                );
+                continue;
+            };
+            let original = format!("原金额: {:.2} {}", record.amount, record.currency);
+            record.remark = if record.remark.is_empty() {
+                original
+            } else {
+                format!("{} ({})", record.remark, original)
+            };
+            record.amount = (record.amount * rate * 100.0).round() / 100.0;
+            record.currency = "CNY".to_string();
+            record.rule_hits.push(format!("汇率:{}", rate));
+            count += 1;
+        }
+        count
+    }
+}
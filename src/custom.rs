@@ -0,0 +1,337 @@
+use csv::ReaderBuilder;
+use encoding_rs::Encoding;
+use log::{debug, info};
+use regex::Regex;
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::arguments::{self, User};
+use crate::category;
+use crate::currency::{detect_currency, normalize_currency};
+use crate::datetime::{china_timezone, parse_date_time};
+use crate::encoding::{open_input, parse_encoding_label};
+use crate::{DynResult, OutputRecord};
+
+// declarative definition of a csv bill source, loaded from a toml file
+#[derive(Deserialize, Debug)]
+pub struct SourceDefinition {
+    // source name written to the "来源" column
+    pub name: String,
+    // encoding label of the bill, detected if not set
+    #[serde(default)]
+    pub encoding: Option<String>,
+    // the header row is the first row with a cell containing this text,
+    // the first row is the header if not set
+    #[serde(default)]
+    pub header_marker: Option<String>,
+    // rows matching any of these regex patterns are skipped, matched against
+    // the row joined by ','
+    #[serde(default)]
+    pub skip_patterns: Vec<String>,
+    // account of all records, used when there is no account column
+    #[serde(default)]
+    pub account: Option<String>,
+    // currency of all records, used when neither a currency column nor a
+    // currency marker in the amount is found, CNY if not set
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub sign: SignConvention,
+    pub columns: Columns,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Columns {
+    pub time: ColumnRef,
+    pub amount: ColumnRef,
+    #[serde(default)]
+    pub direction: Option<ColumnRef>,
+    #[serde(default)]
+    pub counterparty: Option<ColumnRef>,
+    #[serde(default)]
+    pub account: Option<ColumnRef>,
+    #[serde(default)]
+    pub remark: Option<ColumnRef>,
+    #[serde(default)]
+    pub transaction_id: Option<ColumnRef>,
+    #[serde(default)]
+    pub currency: Option<ColumnRef>,
+}
+
+// column given by zero-based index or by header name
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ColumnRef {
+    Index(usize),
+    Name(String),
+}
+
+// how to tell expense from income
+#[derive(Deserialize, Debug, Default)]
+#[serde(tag = "convention", rename_all = "snake_case")]
+pub enum SignConvention {
+    // negative amounts are expense, e.g. debit card statements
+    #[default]
+    NegativeExpense,
+    // negative amounts are income, e.g. credit card statements
+    NegativeIncome,
+    // the direction column tells expense from income
+    Direction {
+        income: Vec<String>,
+        expense: Vec<String>,
+    },
+}
+
+impl SourceDefinition {
+    pub fn load(definition_file: &Path) -> DynResult<SourceDefinition> {
+        info!("读取账单来源定义: {}", definition_file.display());
+        let content = std::fs::read_to_string(definition_file)?;
+        let definition: SourceDefinition = toml::from_str(&content).map_err(|e| {
+            format!(
+                "{}: 账单来源定义格式不正确: {}",
+                definition_file.display(),
+                e
+            )
+        })?;
+        if let SignConvention::Direction { .. } = definition.sign {
+            if definition.columns.direction.is_none() {
+                return Err(format!(
+                    "{}: 按收/支列区分方向时必须定义 direction 列",
+                    definition_file.display()
+                )
+                .into());
+            }
+        }
+        // records without an account are rejected by the output check
+        let has_account = definition.account.as_ref().is_some_and(|a| !a.is_empty());
+        if definition.columns.account.is_none() && !has_account {
+            return Err(format!(
+                "{}: 没有 account 列时必须定义 account",
+                definition_file.display()
+            )
+            .into());
+        }
+        Ok(definition)
+    }
+}
+
+// column indexes resolved from the header row
+struct ResolvedColumns {
+    time: usize,
+    amount: usize,
+    direction: Option<usize>,
+    counterparty: Option<usize>,
+    account: Option<usize>,
+    remark: Option<usize>,
+    transaction_id: Option<usize>,
+    currency: Option<usize>,
+}
+
+impl ResolvedColumns {
+    fn resolve(columns: &Columns, header: &csv::StringRecord) -> DynResult<ResolvedColumns> {
+        let find = |column: &ColumnRef| -> DynResult<usize> {
+            match column {
+                ColumnRef::Index(index) => Ok(*index),
+                ColumnRef::Name(name) => header
+                    .iter()
+                    .position(|s| s == name)
+                    .ok_or_else(|| format!("账单缺少列: {}", name).into()),
+            }
+        };
+        let find_optional = |column: &Option<ColumnRef>| column.as_ref().map(find).transpose();
+        Ok(ResolvedColumns {
+            time: find(&columns.time)?,
+            amount: find(&columns.amount)?,
+            direction: find_optional(&columns.direction)?,
+            counterparty: find_optional(&columns.counterparty)?,
+            account: find_optional(&columns.account)?,
+            remark: find_optional(&columns.remark)?,
+            transaction_id: find_optional(&columns.transaction_id)?,
+            currency: find_optional(&columns.currency)?,
+        })
+    }
+}
+
+pub fn read_input_file(
+    definition: &SourceDefinition,
+    input_file: &Path,
+    user: &User,
+    encoding: Option<&'static Encoding>,
+) -> DynResult<Vec<OutputRecord>> {
+    // an encoding given on the command line wins over the definition
+    let encoding = match (encoding, &definition.encoding) {
+        (Some(encoding), _) => Some(encoding),
+        (None, Some(label)) => Some(parse_encoding_label(label)?),
+        (None, None) => None,
+    };
+    let decoder = open_input(input_file, encoding)?;
+
+    // create csv reader
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(decoder);
+
+    let skip_patterns = definition
+        .skip_patterns
+        .iter()
+        .map(|p| Regex::new(p))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut records = Vec::new();
+    let mut columns: Option<ResolvedColumns> = None;
+    let source = definition.name.as_str();
+
+    for result in rdr.records() {
+        let record = result?;
+
+        // find the header line in csv records
+        let columns = match &columns {
+            Some(columns) => columns,
+            None => {
+                let is_header = match &definition.header_marker {
+                    Some(marker) => record.iter().any(|s| s.contains(marker.as_str())),
+                    None => true,
+                };
+                if is_header {
+                    columns = Some(ResolvedColumns::resolve(&definition.columns, &record)?);
+                }
+                continue;
+            }
+        };
+
+        // ignore rows matching skip patterns
+        let line = record.iter().collect::<Vec<_>>().join(",");
+        if line.trim_matches(',').is_empty() || skip_patterns.iter().any(|re| re.is_match(&line)) {
+            debug!("{}: 跳过行: {:?}", source, record);
+            continue;
+        }
+
+        // get items
+        let get = |index: Option<usize>| index.and_then(|i| record.get(i)).unwrap_or("");
+        let transaction_time = get(Some(columns.time)).to_string();
+        let amount_str = get(Some(columns.amount));
+        let signed_amount = amount_str
+            .chars()
+            .filter(|c| c.is_ascii_digit() || *c == '.' || *c == '-')
+            .collect::<String>()
+            .parse::<f32>()
+            .map_err(|e| {
+                format!(
+                    "{} {}: 不支持的金额输入格式: {}",
+                    transaction_time, source, e
+                )
+            })?;
+        let amount = signed_amount.abs();
+        let counterparty = get(columns.counterparty).to_string();
+        let remark = get(columns.remark).to_string();
+        let transaction_id = get(columns.transaction_id).to_string();
+        let currency = normalize_currency(get(columns.currency))
+            .or_else(|| detect_currency(amount_str))
+            .map(|c| c.to_string())
+            .or_else(|| definition.currency.clone())
+            .unwrap_or_else(|| "CNY".to_string());
+        let account_from = match columns.account {
+            Some(_) => get(columns.account).to_string(),
+            None => definition.account.clone().unwrap_or_default(),
+        };
+
+        // direction setting
+        let transaction_type = match &definition.sign {
+            SignConvention::NegativeExpense if signed_amount < 0.0 => "支出",
+            SignConvention::NegativeExpense => "收入",
+            SignConvention::NegativeIncome if signed_amount < 0.0 => "收入",
+            SignConvention::NegativeIncome => "支出",
+            SignConvention::Direction { income, expense } => {
+                let direction = get(columns.direction);
+                if income.iter().any(|s| s == direction) {
+                    "收入"
+                } else if expense.iter().any(|s| s == direction) {
+                    "支出"
+                } else {
+                    debug!(
+                        "{} {}: 跳过未知方向交易: {:?}",
+                        transaction_time, source, record
+                    );
+                    continue;
+                }
+            }
+        }
+        .to_string();
+
+        if amount == 0.0 {
+            debug!(
+                "{} {}: 跳过金额为0的交易: {:?}",
+                transaction_time, source, record
+            );
+            continue;
+        }
+
+        // category setting
+        let mut rule_hits = Vec::new();
+        let (category1, category2) = category::filter_category(
+            &counterparty,
+            &remark,
+            &transaction_type,
+            amount,
+            &mut rule_hits,
+        );
+
+        // prepare remarks
+        let remark = if counterparty.is_empty() {
+            remark
+        } else {
+            remark + ": " + &counterparty
+        };
+
+        // parse date-time
+        let date = parse_date_time(&transaction_time)
+            .map_err(|e| format!("{} {}: {}", transaction_time, source, e))?;
+
+        let output_record = OutputRecord {
+            date,
+            timezone: china_timezone(),
+            r#type: transaction_type,
+            amount,
+            category1,
+            category2,
+            account1: account_from,
+            account2: String::new(),
+            remark,
+            currency,
+            tag: String::new(),
+            source: source.to_string(),
+            user: user.clone(),
+            counterparty,
+            transaction_id,
+            row: record.position().map_or(0, |p| p.line()),
+            raw: record.iter().map(|s| s.to_string()).collect(),
+            rule_hits,
+        };
+
+        records.push(output_record);
+    }
+
+    Ok(records)
+}
+
+pub fn handle_bill(
+    user: &arguments::User,
+    records: &mut Vec<OutputRecord>,
+    definition_file: &Path,
+    input_file: &Path,
+    encoding: Option<&'static Encoding>,
+) {
+    let definition = SourceDefinition::load(definition_file).expect("read source definition error");
+    info!("处理账单文件: {}", input_file.display());
+    println!("处理{}账单: {}", definition.name, input_file.display());
+    let current_records = read_input_file(&definition, input_file, user, encoding)
+        .expect("read input csv file error");
+    println!(
+        "处理{}账单条目数量: {}",
+        definition.name,
+        current_records.len()
+    );
+    records.extend(current_records);
+}
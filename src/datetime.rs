@@ -0,0 +1,92 @@
+use chrono::{FixedOffset, NaiveDate, NaiveDateTime};
+
+use crate::DynResult;
+
+// date-time formats seen in bill exports, month/day/hour may be unpadded
+const DATE_TIME_FORMATS: [&str; 8] = [
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%Y/%m/%d %H:%M:%S",
+    "%Y/%m/%d %H:%M",
+    "%Y年%m月%d日 %H:%M:%S",
+    "%Y年%m月%d日 %H:%M",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y.%m.%d %H:%M:%S",
+];
+
+// date-only formats, parsed as midnight
+const DATE_FORMATS: [&str; 4] = ["%Y-%m-%d", "%Y/%m/%d", "%Y年%m月%d日", "%Y%m%d"];
+
+// parse date-time string of a bill in any supported format
+pub fn parse_date_time(input: &str) -> DynResult<NaiveDateTime> {
+    let input = input.trim();
+    for format in DATE_TIME_FORMATS {
+        if let Ok(date_time) = NaiveDateTime::parse_from_str(input, format) {
+            return Ok(date_time);
+        }
+    }
+    for format in DATE_FORMATS {
+        if let Ok(date) = NaiveDate::parse_from_str(input, format) {
+            return Ok(date.and_hms_opt(0, 0, 0).unwrap());
+        }
+    }
+    Err(format!("日期时间格式不正确: {}", input).into())
+}
+
+// timezone of bills exported by chinese payment platforms
+pub fn china_timezone() -> FixedOffset {
+    FixedOffset::east_opt(8 * 3600).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date_time(y: i32, m: u32, d: u32, h: u32, min: u32, s: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_hms_opt(h, min, s)
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_unpadded_date_time() {
+        assert_eq!(
+            parse_date_time("2024/5/3 9:05").unwrap(),
+            date_time(2024, 5, 3, 9, 5, 0)
+        );
+        assert_eq!(
+            parse_date_time("2024-5-3 9:05:07").unwrap(),
+            date_time(2024, 5, 3, 9, 5, 7)
+        );
+    }
+
+    #[test]
+    fn parses_padded_formats() {
+        let expected = date_time(2024, 5, 3, 9, 5, 7);
+        for input in [
+            "2024-05-03 09:05:07",
+            "2024/05/03 09:05:07",
+            "2024年05月03日 09:05:07",
+            "2024-05-03T09:05:07",
+            "2024.05.03 09:05:07",
+            " 2024-05-03 09:05:07 ",
+        ] {
+            assert_eq!(parse_date_time(input).unwrap(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn parses_date_as_midnight() {
+        let expected = date_time(2024, 5, 3, 0, 0, 0);
+        for input in ["2024-05-03", "2024/5/3", "2024年5月3日", "20240503"] {
+            assert_eq!(parse_date_time(input).unwrap(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_format() {
+        assert!(parse_date_time("05/03/2024").is_err());
+        assert!(parse_date_time("").is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use csv::{Reader, ReaderBuilder, StringRecord};
+use encoding_rs::{Encoding, GB18030, UTF_16BE, UTF_16LE, UTF_8};
+use encoding_rs_io::{DecodeReaderBytes, DecodeReaderBytesBuilder};
+use log::info;
+use std::io::Cursor;
+use std::path::Path;
+
+use crate::DynResult;
+
+pub type InputReader = DecodeReaderBytes<Cursor<Vec<u8>>, Vec<u8>>;
+// finds the column of the first header name present
+pub type FindColumn = Box<dyn Fn(&[&str]) -> DynResult<usize>>;
+
+// bytes looked at when guessing utf-16 without BOM
+const SNIFF_LENGTH: usize = 1024;
+
+// guess the encoding of a bill: BOM first, then utf-16 by the zero bytes of
+// ascii characters, then utf-8 if valid, and GB18030 (a superset of GBK) otherwise
+pub fn detect_encoding(bytes: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+
+    let head = &bytes[..bytes.len().min(SNIFF_LENGTH)];
+    let zeros_at = |parity: usize| {
+        head.iter()
+            .skip(parity)
+            .step_by(2)
+            .filter(|b| **b == 0)
+            .count()
+    };
+    // csv files are mostly ascii digits and separators
+    let half = head.len() / 2;
+    if half > 0 && zeros_at(1) * 2 > half && zeros_at(0) == 0 {
+        return UTF_16LE;
+    }
+    if half > 0 && zeros_at(0) * 2 > half && zeros_at(1) == 0 {
+        return UTF_16BE;
+    }
+
+    if std::str::from_utf8(bytes).is_ok() {
+        UTF_8
+    } else {
+        GB18030
+    }
+}
+
+// open a bill and decode it to utf-8, with the given encoding or a detected one
+pub fn open_input(
+    input_file: &Path,
+    encoding: Option<&'static Encoding>,
+) -> DynResult<InputReader> {
+    let bytes = std::fs::read(input_file)?;
+    let encoding = match encoding {
+        Some(encoding) => encoding,
+        None => {
+            let encoding = detect_encoding(&bytes);
+            info!("{}: 检测到编码 {}", input_file.display(), encoding.name());
+            encoding
+        }
+    };
+    Ok(DecodeReaderBytesBuilder::new()
+        .encoding(Some(encoding))
+        .bom_override(true)
+        .strip_bom(true)
+        .build(Cursor::new(bytes)))
+}
+
+// open a user provided csv with a header row, e.g. the cards or the rates file.
+// header names are matched ignoring ascii case, a missing column is an error
+// naming the file
+pub fn open_csv_with_headers(
+    input_file: &Path,
+    file_name: &'static str,
+) -> DynResult<(Reader<InputReader>, FindColumn)> {
+    let decoder = open_input(input_file, None)?;
+
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(decoder);
+
+    let headers = rdr.headers()?.clone();
+    let find = Box::new(move |names: &[&str]| -> DynResult<usize> {
+        names
+            .iter()
+            .find_map(|n| headers.iter().position(|h| h.eq_ignore_ascii_case(n)))
+            .ok_or_else(|| format!("{}缺少列: {}", file_name, names[0]).into())
+    });
+    Ok((rdr, find))
+}
+
+// column of the first header name present in the header row of a bill
+pub fn find_column(header: &StringRecord, names: &[&str]) -> Option<usize> {
+    names
+        .iter()
+        .find_map(|n| header.iter().position(|s| s == *n))
+}
+
+// column of the first header name present, a missing column is an error
+// naming the bill, e.g. "京东账单缺少列: 交易时间"
+pub fn require_column(header: &StringRecord, names: &[&str], bill: &str) -> DynResult<usize> {
+    find_column(header, names).ok_or_else(|| format!("{}缺少列: {}", bill, names[0]).into())
+}
+
+// encoding label as accepted by browsers, e.g. "gbk", "utf-16le" or "big5"
+pub fn parse_encoding_label(label: &str) -> DynResult<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| format!("不支持的编码: {}", label).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::GBK;
+
+    const SAMPLE: &str = "交易时间,交易对方,金额\n2024-05-03 09:05:00,美团,25.50\n";
+
+    fn utf16(text: &str, little_endian: bool) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|unit| {
+                if little_endian {
+                    unit.to_le_bytes()
+                } else {
+                    unit.to_be_bytes()
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detects_utf8() {
+        assert_eq!(detect_encoding(SAMPLE.as_bytes()), UTF_8);
+    }
+
+    #[test]
+    fn detects_gbk_as_gb18030() {
+        let (bytes, _, _) = GBK.encode(SAMPLE);
+        assert_eq!(detect_encoding(&bytes), GB18030);
+        let (text, _) = GB18030.decode_without_bom_handling(&bytes);
+        assert_eq!(text, SAMPLE);
+    }
+
+    #[test]
+    fn detects_utf16_without_bom() {
+        assert_eq!(detect_encoding(&utf16(SAMPLE, true)), UTF_16LE);
+        assert_eq!(detect_encoding(&utf16(SAMPLE, false)), UTF_16BE);
+    }
+
+    #[test]
+    fn bom_takes_precedence() {
+        let mut bytes = vec![0xEF, 0xBB, 0xBF];
+        bytes.extend_from_slice(SAMPLE.as_bytes());
+        assert_eq!(detect_encoding(&bytes), UTF_8);
+
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend(utf16(SAMPLE, true));
+        assert_eq!(detect_encoding(&bytes), UTF_16LE);
+    }
+
+    #[test]
+    fn empty_input_is_utf8() {
+        assert_eq!(detect_encoding(&[]), UTF_8);
+    }
+}
@@ -0,0 +1,194 @@
+use chrono::{NaiveDateTime, TimeDelta};
+use csv::ReaderBuilder;
+use log::{debug, info};
+use std::path::Path;
+
+use crate::category;
+use crate::datetime::parse_date_time;
+use crate::encoding::open_input;
+use crate::{DynResult, OutputRecord};
+
+// payments are matched to orders paid within this time window
+const MATCH_WINDOW_MINUTES: i64 = 10;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Platform {
+    Meituan,
+    Pinduoduo,
+}
+
+impl Platform {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Meituan => "美团",
+            Platform::Pinduoduo => "拼多多",
+        }
+    }
+
+    // payment records of the platform contain one of these in the counterparty
+    fn counterparty_keywords(&self) -> &'static [&'static str] {
+        match self {
+            Platform::Meituan => &["美团", "三快"],
+            Platform::Pinduoduo => &["拼多多", "寻梦"],
+        }
+    }
+}
+
+// order exported from a shopping platform
+#[derive(Debug)]
+struct Order {
+    time: NaiveDateTime,
+    amount: f32,
+    shop: String,
+    items: String,
+}
+
+// header names used by the order exports, the first found is used
+const TIME_HEADERS: [&str; 3] = ["下单时间", "支付时间", "订单时间"];
+const AMOUNT_HEADERS: [&str; 4] = ["实付金额", "实付款", "支付金额", "金额"];
+const SHOP_HEADERS: [&str; 4] = ["商家名称", "店铺名称", "商家", "店铺"];
+const ITEMS_HEADERS: [&str; 3] = ["商品名称", "商品", "订单内容"];
+const STATUS_HEADERS: [&str; 2] = ["订单状态", "状态"];
+
+fn read_orders(input_file: &Path, platform: Platform) -> DynResult<Vec<Order>> {
+    let decoder = open_input(input_file, None)?;
+
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(decoder);
+
+    let headers = rdr.headers()?.clone();
+    let find = |names: &[&str]| {
+        names
+            .iter()
+            .find_map(|n| headers.iter().position(|h| h == *n))
+    };
+    let time_index =
+        find(&TIME_HEADERS).ok_or_else(|| format!("{}订单缺少下单时间列", platform.name()))?;
+    let amount_index =
+        find(&AMOUNT_HEADERS).ok_or_else(|| format!("{}订单缺少金额列", platform.name()))?;
+    let shop_index = find(&SHOP_HEADERS);
+    let items_index = find(&ITEMS_HEADERS);
+    let status_index = find(&STATUS_HEADERS);
+
+    let mut orders = Vec::new();
+    for result in rdr.records() {
+        let record = result?;
+        let get = |index: Option<usize>| index.and_then(|i| record.get(i)).unwrap_or("");
+
+        let status = get(status_index);
+        if status.contains("取消") || status.contains("关闭") || status.contains("退款") {
+            debug!("{}: 跳过已取消订单: {:?}", platform.name(), record);
+            continue;
+        }
+
+        let time_str = get(Some(time_index));
+        let time = parse_date_time(time_str)
+            .map_err(|e| format!("{} {}订单: {}", time_str, platform.name(), e))?;
+        let amount = get(Some(amount_index))
+            .replace(['¥', '￥', ',', ' '], "")
+            .parse::<f32>()
+            .map_err(|e| {
+                format!(
+                    "{} {}订单: 不支持的金额输入格式: {}",
+                    time_str,
+                    platform.name(),
+                    e
+                )
+            })?;
+
+        orders.push(Order {
+            time,
+            amount,
+            shop: get(shop_index).to_string(),
+            items: get(items_index).to_string(),
+        });
+    }
+
+    Ok(orders)
+}
+
+// category of the ordered goods, these names are too common to be matched
+// against every remark
+fn filter_goods(items: &str) -> Option<(&'static str, &'static str, &'static str)> {
+    if items.contains("外卖") || items.contains("套餐") {
+        Some(("餐饮", "外卖", "商品说明:外卖"))
+    } else if items.contains("蔬菜") || items.contains("水果") || items.contains("生鲜") {
+        Some(("食材", "蔬菜", "商品说明:生鲜"))
+    } else {
+        None
+    }
+}
+
+// replace vague remarks of platform payments with the ordered items, and
+// filter the category again with the shop and items
+pub fn enrich_records(
+    records: &mut [OutputRecord],
+    input_file: &Path,
+    platform: Platform,
+) -> DynResult<usize> {
+    info!("读取{}订单: {}", platform.name(), input_file.display());
+    let orders = read_orders(input_file, platform)?;
+    let window = TimeDelta::minutes(MATCH_WINDOW_MINUTES);
+    let mut matched = vec![false; records.len()];
+    let mut count = 0;
+
+    for order in &orders {
+        // closest payment of the platform with the same amount
+        let candidate = records
+            .iter()
+            .enumerate()
+            .filter(|(i, record)| {
+                !matched[*i]
+                    && record.r#type == "支出"
+                    && (record.amount - order.amount).abs() < 0.005
+                    && (record.date - order.time).abs() <= window
+                    && platform
+                        .counterparty_keywords()
+                        .iter()
+                        .any(|k| record.counterparty.contains(k))
+            })
+            .min_by_key(|(_, record)| (record.date - order.time).abs())
+            .map(|(i, _)| i);
+
+        let Some(index) = candidate else {
+            debug!(
+                "{} {}订单: 没有找到对应的支付记录: {:?}",
+                order.time,
+                platform.name(),
+                order
+            );
+            continue;
+        };
+        matched[index] = true;
+        count += 1;
+
+        let record = &mut records[index];
+        record.remark = format!("{}: {}: {}", platform.name(), order.shop, order.items);
+        let mut rule_hits = Vec::new();
+        let (category1, category2) = category::filter_category(
+            &order.shop,
+            &order.items,
+            &record.r#type,
+            record.amount,
+            &mut rule_hits,
+        );
+        if category1 != "未知" {
+            record.category1 = category1;
+            record.category2 = category2;
+            record.rule_hits = rule_hits;
+        }
+        if record.r#type == "支出" {
+            if let Some((category1, category2, rule)) = filter_goods(&order.items) {
+                record.category1 = category1.to_string();
+                record.category2 = category2.to_string();
+                record.rule_hits.push(rule.to_string());
+            }
+        }
+        record.rule_hits.push(format!("订单:{}", platform.name()));
+    }
+
+    Ok(count)
+}
@@ -0,0 +1,66 @@
+use log::info;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::encoding::open_csv_with_headers;
+use crate::{DynResult, OutputRecord};
+
+// header names used by the family file, the first found is used
+const NAME_HEADERS: [&str; 2] = ["交易对方", "name"];
+const ACCOUNT_HEADERS: [&str; 2] = ["账户", "account"];
+
+// household members by the name shown as counterparty, with the account
+// their red packets are paid from and into, e.g. "韩,零钱-韩"
+pub struct Family {
+    members: HashMap<String, String>,
+}
+
+impl Family {
+    pub fn load(input_file: &Path) -> DynResult<Family> {
+        let (mut rdr, find) = open_csv_with_headers(input_file, "家庭成员文件")?;
+        let name_index = find(&NAME_HEADERS)?;
+        let account_index = find(&ACCOUNT_HEADERS)?;
+
+        let mut members = HashMap::new();
+        for result in rdr.records() {
+            let record = result?;
+            let get = |index: usize| record.get(index).unwrap_or("").to_string();
+            members.insert(get(name_index), get(account_index));
+        }
+        info!("读取家庭成员: {} 人", members.len());
+
+        Ok(Family { members })
+    }
+
+    // red packets sent to or received from family members move money between
+    // household accounts, book them as transfers, returns the number of changed records
+    pub fn apply(&self, records: &mut [OutputRecord]) -> usize {
+        let mut count = 0;
+        for record in records.iter_mut() {
+            if record.category1 != "人情" || record.category2 != "红包" {
+                continue;
+            }
+            let Some(account) = self.members.get(&record.counterparty) else {
+                continue;
+            };
+            if *account == record.account1 {
+                continue;
+            }
+            match record.r#type.as_str() {
+                "收入" => {
+                    record.account2 = std::mem::replace(&mut record.account1, account.clone());
+                }
+                "支出" => record.account2 = account.clone(),
+                _ => continue,
+            }
+            record.r#type = "转账".to_string();
+            record.category1 = String::new();
+            record.category2 = String::new();
+            record
+                .rule_hits
+                .push(format!("家庭成员:{}", record.counterparty));
+            count += 1;
+        }
+        count
+    }
+}
@@ -0,0 +1,238 @@
+use csv::ReaderBuilder;
+use encoding_rs::Encoding;
+use log::{debug, info};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::arguments::User;
+use crate::datetime::{china_timezone, parse_date_time};
+use crate::encoding::{find_column, open_input, require_column};
+use crate::{DynResult, OutputRecord};
+
+// sources whose remarks end with the counterparty
+const COUNTERPARTY_SOURCES: [&str; 3] = ["微信", "云闪付", "PayPal"];
+
+// column indexes of the iCost export, resolved from the header row
+struct Header {
+    date: usize,
+    r#type: usize,
+    amount: usize,
+    category1: Option<usize>,
+    category2: Option<usize>,
+    account1: Option<usize>,
+    account2: Option<usize>,
+    remark: Option<usize>,
+    currency: Option<usize>,
+    tag: Option<usize>,
+    source: Option<usize>,
+}
+
+impl Header {
+    fn resolve(record: &csv::StringRecord) -> DynResult<Header> {
+        let find_optional = |name: &str| find_column(record, &[name]);
+        let find = |name: &str| require_column(record, &[name], "iCost账单");
+        Ok(Header {
+            date: find("日期")?,
+            r#type: find("类型")?,
+            amount: find("金额")?,
+            category1: find_optional("一级分类"),
+            category2: find_optional("二级分类"),
+            account1: find_optional("账户1"),
+            account2: find_optional("账户2"),
+            remark: find_optional("备注"),
+            currency: find_optional("货币"),
+            tag: find_optional("标签"),
+            source: find_optional("来源"),
+        })
+    }
+}
+
+// read an iCost export, the same columns as written by the icost profile
+pub fn read_input_file(
+    input_file: &Path,
+    user: &User,
+    encoding: Option<&'static Encoding>,
+) -> DynResult<Vec<OutputRecord>> {
+    let decoder = open_input(input_file, encoding)?;
+
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(decoder);
+
+    let mut records = Vec::new();
+    let mut header: Option<Header> = None;
+
+    for result in rdr.records() {
+        let record = result?;
+
+        // the first line is the header
+        let header = match &header {
+            Some(header) => header,
+            None => {
+                header = Some(Header::resolve(&record)?);
+                continue;
+            }
+        };
+
+        let get = |index: Option<usize>| index.and_then(|i| record.get(i)).unwrap_or("");
+        let date_str = get(Some(header.date));
+        if date_str.is_empty() {
+            continue;
+        }
+        let source = match get(header.source) {
+            "" => "iCost",
+            s => s,
+        };
+        let date = parse_date_time(date_str).map_err(|e| format!("{} iCost: {}", date_str, e))?;
+        let amount = get(Some(header.amount))
+            .replace([',', ' '], "")
+            .parse::<f32>()
+            .map_err(|e| format!("{} iCost: 不支持的金额输入格式: {}", date_str, e))?;
+        let remark = get(header.remark).to_string();
+        // the counterparty is not exported, only the remarks written by this tool
+        // for these sources end with ": <counterparty>". the others end with the
+        // item or the remark of the bill, they are learned by the remark only
+        let counterparty = if COUNTERPARTY_SOURCES.contains(&source) {
+            remark.rsplit_once(": ").map_or("", |(_, c)| c).to_string()
+        } else {
+            String::new()
+        };
+
+        let mut output_record = OutputRecord {
+            date,
+            timezone: china_timezone(),
+            r#type: get(Some(header.r#type)).to_string(),
+            amount: amount.abs(),
+            category1: get(header.category1).to_string(),
+            category2: get(header.category2).to_string(),
+            account1: get(header.account1).to_string(),
+            account2: get(header.account2).to_string(),
+            remark,
+            currency: get(header.currency).to_string(),
+            tag: get(header.tag).to_string(),
+            source: source.to_string(),
+            user: user.clone(),
+            counterparty,
+            transaction_id: String::new(),
+            row: record.position().map_or(0, |p| p.line()),
+            raw: record.iter().map(|s| s.to_string()).collect(),
+            rule_hits: Vec::new(),
+        };
+        // iCost exports have no transaction number, identify rows by their content
+        output_record.transaction_id = content_id(&output_record);
+
+        records.push(output_record);
+    }
+
+    Ok(records)
+}
+
+// records of the same minute, type, amount and account are the same transaction
+fn duplicate_key(record: &OutputRecord) -> (String, String, i64, String) {
+    (
+        record.date.format("%Y-%m-%d %H:%M").to_string(),
+        record.r#type.clone(),
+        (record.amount * 100.0).round() as i64,
+        record.account1.clone(),
+    )
+}
+
+// identifier of a record without a transaction number, from its content
+pub fn content_id(record: &OutputRecord) -> String {
+    let (date, r#type, cents, account) = duplicate_key(record);
+    format!("{}|{}|{}|{}", date, r#type, cents, account)
+}
+
+// drop new records already booked in the history, returns the number of dropped records
+pub fn remove_duplicates(records: &mut Vec<OutputRecord>, history: &[OutputRecord]) -> usize {
+    let booked: HashSet<_> = history.iter().map(duplicate_key).collect();
+    let before = records.len();
+    records.retain(|record| {
+        let duplicate = booked.contains(&duplicate_key(record));
+        if duplicate {
+            debug!(
+                "{} {}: 跳过已在iCost中记账的交易: {}",
+                record.date, record.source, record.remark
+            );
+        }
+        !duplicate
+    });
+    before - records.len()
+}
+
+// categories used in the history, by counterparty and by remark
+#[derive(Default)]
+pub struct CategoryHistory {
+    by_counterparty: HashMap<String, HashMap<(String, String), usize>>,
+    by_remark: HashMap<String, HashMap<(String, String), usize>>,
+}
+
+impl CategoryHistory {
+    pub fn learn(history: &[OutputRecord]) -> CategoryHistory {
+        let mut categories = CategoryHistory::default();
+        for record in history {
+            if record.category1.is_empty() || record.category1 == "未知" {
+                continue;
+            }
+            let category = (record.category1.clone(), record.category2.clone());
+            if !record.counterparty.is_empty() && record.counterparty != "/" {
+                *categories
+                    .by_counterparty
+                    .entry(record.counterparty.clone())
+                    .or_default()
+                    .entry(category.clone())
+                    .or_default() += 1;
+            }
+            if !record.remark.is_empty() {
+                *categories
+                    .by_remark
+                    .entry(record.remark.clone())
+                    .or_default()
+                    .entry(category)
+                    .or_default() += 1;
+            }
+        }
+        info!(
+            "从历史账单学习分类: {} 个交易对方, {} 条备注",
+            categories.by_counterparty.len(),
+            categories.by_remark.len()
+        );
+        categories
+    }
+
+    // most used category of the same counterparty, or of the same remark
+    fn suggest(&self, record: &OutputRecord) -> Option<((String, String), String)> {
+        let most_used = |counts: &HashMap<(String, String), usize>| {
+            counts
+                .iter()
+                .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+                .map(|(category, _)| category.clone())
+        };
+        if let Some(counts) = self.by_counterparty.get(&record.counterparty) {
+            return most_used(counts).map(|c| (c, format!("历史交易对方:{}", record.counterparty)));
+        }
+        if let Some(counts) = self.by_remark.get(&record.remark) {
+            return most_used(counts).map(|c| (c, format!("历史备注:{}", record.remark)));
+        }
+        None
+    }
+
+    // fill unknown categories from the history, returns the number of filled records
+    pub fn apply(&self, records: &mut [OutputRecord]) -> usize {
+        let mut count = 0;
+        for record in records.iter_mut() {
+            if record.category1 != "未知" {
+                continue;
+            }
+            if let Some(((category1, category2), rule)) = self.suggest(record) {
+                record.category1 = category1;
+                record.category2 = category2;
+                record.rule_hits.push(rule);
+                count += 1;
+            }
+        }
+        count
+    }
+}
@@ -0,0 +1,244 @@
+use csv::ReaderBuilder;
+use encoding_rs::Encoding;
+use log::{debug, info};
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+use crate::arguments::{self, User};
+use crate::category;
+use crate::currency::detect_currency;
+use crate::datetime::{china_timezone, parse_date_time};
+use crate::encoding::{find_column, open_input, require_column};
+use crate::{DynResult, OutputRecord};
+
+// column indexes of the export, resolved from the header row
+struct Header {
+    time: usize,
+    counterparty: usize,
+    description: usize,
+    amount: usize,
+    method: usize,
+    status: usize,
+    direction: usize,
+    transaction_id: usize,
+    remark: Option<usize>,
+}
+
+impl Header {
+    fn is_header(record: &csv::StringRecord) -> bool {
+        record.get(0).is_some_and(|s| s.contains("交易时间"))
+    }
+
+    fn resolve(record: &csv::StringRecord) -> DynResult<Header> {
+        let find_optional = |names: &[&str]| find_column(record, names);
+        let find = |names: &[&str]| require_column(record, names, "京东账单");
+        Ok(Header {
+            time: find(&["交易时间"])?,
+            counterparty: find(&["商户名称"])?,
+            description: find(&["交易说明"])?,
+            amount: find(&["金额"])?,
+            method: find(&["收/付款方式"])?,
+            status: find(&["交易状态"])?,
+            direction: find(&["收/支"])?,
+            transaction_id: find(&["交易订单号"])?,
+            remark: find_optional(&["备注"]),
+        })
+    }
+}
+
+pub fn read_input_file(
+    input_file: &Path,
+    user: &User,
+    encoding: Option<&'static Encoding>,
+) -> DynResult<Vec<OutputRecord>> {
+    let decoder = open_input(input_file, encoding)?;
+
+    // create csv reader
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(decoder);
+
+    let mut records = Vec::new();
+    let mut header: Option<Header> = None;
+    let re = Regex::new(r"^[^\d]*([\d,]+(\.\d+)?)").unwrap(); // leading number of the amount column
+
+    for result in rdr.records() {
+        let record = result?;
+
+        // find the first normal line in csv records
+        let header = match &header {
+            Some(header) => header,
+            None => {
+                if Header::is_header(&record) {
+                    header = Some(Header::resolve(&record)?);
+                }
+                continue;
+            }
+        };
+
+        // get items
+        let source = "京东";
+        let get = |index: usize| record.get(index).unwrap_or("");
+        let transaction_time = get(header.time).to_string();
+        let counterparty = get(header.counterparty).to_string();
+        let description = get(header.description).to_string();
+        let amount_str = get(header.amount);
+        // used for income/outcome account and the source account of transfer
+        let mut account_from = get(header.method).to_string();
+        // only used for transfer item, as the target account
+        let mut account_to = String::from("");
+        let status = get(header.status).to_string();
+        let mut transaction_type = get(header.direction).to_string();
+        let transaction_id = get(header.transaction_id).to_string();
+        let remark = header.remark.map_or("", get).to_string();
+
+        let amount = re
+            .captures(amount_str)
+            .and_then(|c| c.get(1))
+            .map_or("", |m| m.as_str())
+            .replace(",", "")
+            .parse::<f32>()
+            .map_err(|e| {
+                format!(
+                    "{} {}: 不支持的金额输入格式: {}",
+                    transaction_time, source, e
+                )
+            })?;
+
+        // ignore some items
+        if amount_str.contains("已全额退款") {
+            debug!(
+                "{} {}: 跳过全额退款交易: {:?}",
+                transaction_time, source, record
+            );
+            continue;
+        }
+        if status == "交易关闭" || status == "已关闭" || status == "交易失败" {
+            debug!(
+                "{} {}: 跳过已关闭交易: {:?}",
+                transaction_time, source, record
+            );
+            continue;
+        }
+
+        // handle special items
+        let mut category_fixed = None;
+        if description.contains("白条") && description.contains("还款") {
+            // repay 白条 from the paying account
+            transaction_type = "转账".to_string();
+            account_to = "京东白条".to_string();
+        } else if description.contains("分期")
+            && (description.contains("服务费") || description.contains("手续费"))
+        {
+            transaction_type = "支出".to_string();
+            category_fixed = Some(("金融".to_string(), "分期手续费".to_string()));
+        } else if status == "退款成功" || description.contains("退款") {
+            transaction_type = "退款".to_string();
+        } else if transaction_type == "不计收支" {
+            debug!(
+                "{} {}: 跳过其他不计收支交易: {:?}",
+                transaction_time, source, record
+            );
+            continue;
+        }
+
+        if amount == 0.0 {
+            debug!(
+                "{} {}: 跳过金额为0的交易: {:?}",
+                transaction_time, source, record
+            );
+            continue;
+        }
+
+        // 白条 is a credit account, purchases charged to it are booked on it
+        if account_from.contains("白条") {
+            account_from = "京东白条".to_string();
+        }
+        account_from = append_user_postfix(&account_from, user);
+        account_to = append_user_postfix(&account_to, user);
+
+        // category setting
+        let mut rule_hits = Vec::new();
+        let (category1, category2) = match category_fixed {
+            Some(category) => {
+                rule_hits.push("交易说明:分期手续费".to_string());
+                category
+            }
+            None => category::filter_category(
+                &counterparty,
+                &description,
+                &transaction_type,
+                amount,
+                &mut rule_hits,
+            ),
+        };
+
+        // prepare remarks
+        let remark = if remark.is_empty() || remark == "/" {
+            description + ": " + &counterparty
+        } else {
+            description + ": " + &counterparty + ": " + &remark
+        };
+
+        // parse date-time
+        let date = parse_date_time(&transaction_time)
+            .map_err(|e| format!("{} {}: {}", transaction_time, source, e))?;
+
+        let output_record = OutputRecord {
+            date,
+            timezone: china_timezone(),
+            r#type: transaction_type,
+            amount,
+            category1,
+            category2,
+            account1: account_from,
+            account2: account_to,
+            remark,
+            currency: detect_currency(amount_str).unwrap_or("CNY").to_string(),
+            tag: String::new(),
+            source: String::from(source),
+            user: user.clone(),
+            counterparty,
+            transaction_id,
+            row: record.position().map_or(0, |p| p.line()),
+            raw: record.iter().map(|s| s.to_string()).collect(),
+            rule_hits,
+        };
+
+        records.push(output_record);
+    }
+
+    Ok(records)
+}
+
+fn append_user_postfix(account: &str, user: &User) -> String {
+    let mut account = account.to_string();
+    if account.contains("小金库") {
+        account = String::from("京东小金库");
+    }
+    if !(account == "京东白条" || account == "京东小金库") {
+        return account.to_string();
+    }
+
+    match user {
+        User::Yang => account.to_string() + "-杨",
+        User::Han => account.to_string() + "-韩",
+    }
+}
+
+pub fn handle_bill(
+    user: &arguments::User,
+    records: &mut Vec<OutputRecord>,
+    input_file: &PathBuf,
+    encoding: Option<&'static Encoding>,
+) {
+    let input_file = Path::new(input_file);
+    info!("处理账单文件: {}", input_file.display());
+    println!("处理京东账单: {}", input_file.display());
+    let current_records =
+        read_input_file(input_file, user, encoding).expect("read input csv file error");
+    println!("处理京东账单条目数量: {}", current_records.len());
+    records.extend(current_records);
+}
@@ -1,19 +1,72 @@
 use clap::{self, Parser};
 use std::error::Error;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 mod arguments;
 use arguments::Args;
 
+mod archive;
+use archive::Archive;
+
 mod zhifubao;
 use zhifubao::handle_bill as zhifubao_handle;
 
 mod weixin;
 use weixin::handle_bill as weixin_handle;
 
+mod jingdong;
+use jingdong::handle_bill as jingdong_handle;
+
+mod yunshanfu;
+use yunshanfu::handle_bill as yunshanfu_handle;
+
+mod zhaoshang;
+use zhaoshang::handle_bill as zhaoshang_handle;
+
+mod paypal;
+use paypal::handle_bill as paypal_handle;
+
+mod custom;
+use custom::handle_bill as custom_handle;
+
+mod category;
+
+mod cards;
+use cards::CardAccounts;
+
+mod family;
+use family::Family;
+
+mod currency;
+use currency::RateTable;
+
+mod icost;
+use icost::CategoryHistory;
+
+mod enrich;
+use enrich::{enrich_records, Platform};
+
+mod preamble;
+
+mod yields;
+use yields::aggregate_yields;
+
+mod reimburse;
+use reimburse::Reimbursements;
+
+mod reconcile;
+use reconcile::Balances;
+
 mod output;
 use output::OutputRecord;
 
+mod datetime;
+
+mod encoding;
+
+mod template;
+use template::Template;
+
 type DynResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -22,34 +75,198 @@ fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
 
     // get input bill path
-    let zfb_bill = args.zhifubao_bill;
-    let wx_bill = args.weixin_bill;
-    if zfb_bill.is_none() && wx_bill.is_none() {
+    let mut inputs: Vec<&Path> = [
+        &args.zhifubao_bill,
+        &args.weixin_bill,
+        &args.jingdong_bill,
+        &args.yunshanfu_bill,
+        &args.zhaoshang_bill,
+        &args.paypal_bill,
+        &args.icost_history,
+    ]
+    .iter()
+    .filter_map(|bill| bill.as_deref())
+    .collect();
+    inputs.extend(args.custom_bills.iter().map(|bill| bill.bill.as_path()));
+    if inputs.is_empty() {
         println!("请提供至少一个账单文件");
         return Ok(());
     }
 
     // set output bill path
-    let output_file = &args.output.unwrap_or(PathBuf::from("output.csv"));
+    let output_file = &args.output.clone().unwrap_or(PathBuf::from("output.csv"));
     let user = &args.user;
 
+    // output template, a template file overrides the built-in profile
+    let template = match &args.template {
+        Some(template_file) => Template::load(template_file).expect("read output template error"),
+        None => Template::from_profile(&args.profile),
+    };
+
     // save records
     let mut records: Vec<OutputRecord> = Vec::new();
 
-    if let Some(zfb_bill) = zfb_bill {
-        zhifubao_handle(user, &mut records, &zfb_bill);
+    if let Some(zfb_bill) = &args.zhifubao_bill {
+        zhifubao_handle(user, &mut records, zfb_bill, args.encoding("zfb", zfb_bill));
+    }
+
+    if let Some(wx_bill) = &args.weixin_bill {
+        weixin_handle(user, &mut records, wx_bill, args.encoding("wx", wx_bill));
+    }
+
+    if let Some(jd_bill) = &args.jingdong_bill {
+        jingdong_handle(user, &mut records, jd_bill, args.encoding("jd", jd_bill));
+    }
+
+    if let Some(ysf_bill) = &args.yunshanfu_bill {
+        yunshanfu_handle(user, &mut records, ysf_bill, args.encoding("ysf", ysf_bill));
+    }
+
+    if let Some(cmb_bill) = &args.zhaoshang_bill {
+        zhaoshang_handle(user, &mut records, cmb_bill, args.encoding("cmb", cmb_bill));
+    }
+
+    if let Some(paypal_bill) = &args.paypal_bill {
+        paypal_handle(
+            user,
+            &mut records,
+            paypal_bill,
+            args.encoding("paypal", paypal_bill),
+        );
+    }
+
+    for custom_bill in &args.custom_bills {
+        custom_handle(
+            user,
+            &mut records,
+            &custom_bill.definition,
+            &custom_bill.bill,
+            args.encoding("custom", &custom_bill.bill),
+        );
+    }
+
+    // enrich platform payments with the ordered items
+    for (orders, platform) in [
+        (&args.meituan_orders, Platform::Meituan),
+        (&args.pinduoduo_orders, Platform::Pinduoduo),
+    ] {
+        if let Some(orders) = orders {
+            let count =
+                enrich_records(&mut records, orders, platform).expect("read order csv file error");
+            println!("匹配{}订单数: {}", platform.name(), count);
+        }
+    }
+
+    // find the target card of credit card repayments and withdrawals
+    let card_accounts = match &args.cards {
+        Some(cards_file) => CardAccounts::load(cards_file).expect("read cards csv file error"),
+        None => CardAccounts::new(),
+    };
+    let resolved = card_accounts.apply(&mut records);
+    println!("识别转账目标卡数: {}", resolved);
+
+    // red packets between household members are transfers
+    if let Some(family_file) = &args.family {
+        let family = Family::load(family_file).expect("read family csv file error");
+        let count = family.apply(&mut records);
+        println!("家庭成员间红包转账数: {}", count);
+    }
+
+    // convert foreign currency records before comparing them with the CNY history
+    if let Some(rates_file) = &args.rates {
+        let rates = RateTable::load(rates_file).expect("read exchange rate csv file error");
+        let count = rates.convert(&mut records);
+        println!("换算外币记录数: {}", count);
+    }
+
+    // records in the date window only, applied again after merging the history
+    let since = args.since.as_ref().map(|d| d.first_day());
+    let until = args.until.as_ref().map(|d| d.last_day());
+    let in_window = |record: &OutputRecord| {
+        let date = record.date.date();
+        since.is_none_or(|since| date >= since) && until.is_none_or(|until| date <= until)
+    };
+
+    // fold daily fund yields before comparing them with the history, only the
+    // days inside the date window are folded
+    if let Some(period) = &args.aggregate_yield {
+        records.retain(in_window);
+        let count = aggregate_yields(&mut records, period, since, until);
+        println!("合并收益记录数: {}", count);
     }
 
-    if let Some(wx_bill) = wx_bill {
-        weixin_handle(user, &mut records, &wx_bill);
+    // book group expenses with the net share, before comparing them with the history
+    if args.link_aa {
+        let count = reimburse::link_aa_shares(&mut records);
+        println!("关联AA收款数: {}", count);
+    }
+
+    // deduplicate against the existing iCost books and learn categories from them
+    if let Some(history_file) = &args.icost_history {
+        println!("处理iCost账单: {}", history_file.display());
+        let history =
+            icost::read_input_file(history_file, user, args.encoding("icost", history_file))
+                .expect("read icost csv file error");
+        println!("处理iCost账单条目数量: {}", history.len());
+        let dropped = icost::remove_duplicates(&mut records, &history);
+        println!("跳过已记账记录数: {}", dropped);
+        let filled = CategoryHistory::learn(&history).apply(&mut records);
+        println!("根据历史账单补全分类数: {}", filled);
+        if args.merge_history {
+            records.extend(history);
+        }
+    }
+
+    // keep records in the date window only
+    if since.is_some() || until.is_some() {
+        records.retain(in_window);
+        println!("日期范围内的记录数: {}", records.len());
+    }
+
+    // tag expenses to be reimbursed and settle the ones paid back
+    if !args.reimbursable.is_empty() {
+        let count = reimburse::tag_reimbursable(&mut records, &args.reimbursable)
+            .expect("invalid reimbursable pattern");
+        println!("待报销支出数: {}", count);
+    }
+    if let Some(state_file) = &args.reimburse_state {
+        let mut reimbursements =
+            Reimbursements::load(state_file).expect("read reimbursement state error");
+        let settled = reimbursements.track(&mut records);
+        println!("已报销记录数: {}", settled);
+        reimbursements.report();
+        reimbursements
+            .save(state_file)
+            .expect("write reimbursement state error");
     }
 
     assert!(!records.is_empty(), "没有读取到任何记录");
 
     // combine all bills
-    OutputRecord::sort_by_time(&mut records);
+    OutputRecord::sort_by_time(&mut records, args.ascending);
     OutputRecord::check(&records);
-    OutputRecord::write(output_file, &records).expect("write to new csv file error");
+    match &args.split_by {
+        Some(split_by) => OutputRecord::write_split(output_file, &records, &template, split_by)
+            .expect("write to split csv files error"),
+        None => OutputRecord::write(output_file, &records, &template)
+            .expect("write to new csv file error"),
+    }
+
+    // check the running balances against the statements
+    if let Some(balances_file) = &args.reconcile {
+        let balances = Balances::load(balances_file).expect("read balances csv file error");
+        let mismatched = balances.reconcile(&records);
+        println!("对账不一致的账户数: {}", mismatched);
+    }
+
+    // archive records into sqlite database
+    if let Some(db_file) = &args.database {
+        let mut archive = Archive::open(db_file).expect("open sqlite archive error");
+        let count = archive
+            .save(user.name(), &inputs, output_file, &records)
+            .expect("write to sqlite archive error");
+        println!("归档记录数: {}", count);
+    }
 
     // summary records
     let mut input_type_count = 0;
@@ -67,6 +284,11 @@ fn main() -> Result<(), Box<dyn Error>> {
                     "{} {}: 转账记录缺少目标账户，请手动添加",
                     record.date, record.source
                 );
+            } else if record.account1 == "未知" {
+                println!(
+                    "{} {}: 转账记录缺少来源账户，请手动添加",
+                    record.date, record.source
+                );
             }
         } else {
             println!(
@@ -74,6 +296,13 @@ fn main() -> Result<(), Box<dyn Error>> {
                 record.date, record.source, record.r#type
             );
         }
+        // e.g. the older Alipay export has no payment method
+        if record.r#type != "转账" && record.account1 == "未知" {
+            println!(
+                "{} {}: 记录缺少账户，请手动添加: {}",
+                record.date, record.source, record.remark
+            );
+        }
     }
     println!("汇总：");
     println!("支出记录数: {}", input_type_count);
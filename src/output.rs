@@ -1,40 +1,94 @@
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
 use csv::WriterBuilder;
 use log::{error, warn};
-use serde::Serialize;
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
+use crate::arguments::{SplitBy, User};
+use crate::template::Template;
 use crate::DynResult;
 
-// record structure for output, use Serialize trait to support CSV serialization
-#[derive(Serialize, Debug)]
+// record structure for output, columns and header names are defined by `Template`
+#[derive(Debug)]
 pub struct OutputRecord {
-    #[serde(rename = "日期")]
-    pub date: String,
-    #[serde(rename = "类型")]
+    // 日期, local time of the source
+    pub date: NaiveDateTime,
+    // timezone of `date`
+    pub timezone: FixedOffset,
+    // 类型
     pub r#type: String,
-    #[serde(rename = "金额")]
+    // 金额
     pub amount: f32,
-    #[serde(rename = "一级分类")]
+    // 一级分类
     pub category1: String,
-    #[serde(rename = "二级分类")]
+    // 二级分类
     pub category2: String,
-    #[serde(rename = "账户1")]
+    // 账户1
     pub account1: String,
-    #[serde(rename = "账户2")]
+    // 账户2
     pub account2: String,
-    #[serde(rename = "备注")]
+    // 备注
     pub remark: String,
-    #[serde(rename = "货币")]
+    // 货币
     pub currency: String,
-    #[serde(rename = "标签")]
+    // 标签
     pub tag: String,
-    #[serde(rename = "来源")]
+    // 来源
     pub source: String,
+    // user who owns the source bill
+    pub user: User,
+    // counterparty name in the source bill
+    pub counterparty: String,
+    // transaction number in the source bill, used as the archive key
+    pub transaction_id: String,
+    // line number of the record in the source bill
+    pub row: u64,
+    // original csv row in the source bill
+    pub raw: Vec<String>,
+    // names of the category rules matched by this record
+    pub rule_hits: Vec<String>,
 }
 
 impl OutputRecord {
-    pub fn sort_by_time(records: &mut [OutputRecord]) {
-        records.sort_by(|a, b| b.date.cmp(&a.date));
+    // date-time of the record with its source timezone
+    pub fn date_time(&self) -> DateTime<FixedOffset> {
+        self.date
+            .and_local_timezone(self.timezone)
+            .single()
+            .unwrap()
+    }
+
+    // amount with sign convention of the account1 side, expense is negative
+    pub fn signed_amount(&self) -> f32 {
+        match self.r#type.as_str() {
+            "收入" | "退款" => self.amount,
+            _ => -self.amount,
+        }
+    }
+
+    // join category levels as "一级分类/二级分类"
+    pub fn category(&self) -> String {
+        if self.category2.is_empty() {
+            self.category1.clone()
+        } else {
+            format!("{}/{}", self.category1, self.category2)
+        }
+    }
+
+    // sort by time, records at the same time are ordered by source, transaction
+    // number and line number so that every run gives the same order
+    pub fn sort_by_time(records: &mut [OutputRecord], ascending: bool) {
+        records.sort_by(|a, b| {
+            let by_time = if ascending {
+                a.date_time().cmp(&b.date_time())
+            } else {
+                b.date_time().cmp(&a.date_time())
+            };
+            by_time
+                .then_with(|| a.source.cmp(&b.source))
+                .then_with(|| a.transaction_id.cmp(&b.transaction_id))
+                .then_with(|| a.row.cmp(&b.row))
+        });
     }
 
     pub fn check(records: &[OutputRecord]) {
@@ -99,15 +153,80 @@ impl OutputRecord {
         }
     }
 
-    pub fn write(output_file: &Path, records: &Vec<OutputRecord>) -> DynResult<()> {
-        println!("写入输出文件: {}", output_file.display());
-        let mut wtr = WriterBuilder::new().from_path(output_file)?;
+    pub fn write(
+        output_file: &Path,
+        records: &[OutputRecord],
+        template: &Template,
+    ) -> DynResult<()> {
+        write_records(output_file, records.iter(), template)
+    }
 
+    // write records into one file per group, and a manifest of all written files
+    pub fn write_split(
+        output_file: &Path,
+        records: &[OutputRecord],
+        template: &Template,
+        split_by: &SplitBy,
+    ) -> DynResult<()> {
+        let mut groups: BTreeMap<String, Vec<&OutputRecord>> = BTreeMap::new();
         for record in records {
-            wtr.serialize(record)?;
+            let key = match split_by {
+                SplitBy::Month => record.date.format("%Y-%m").to_string(),
+                SplitBy::User => record.user.name().to_string(),
+                SplitBy::Account => record.account1.clone(),
+                SplitBy::Source => record.source.clone(),
+            };
+            groups.entry(key).or_default().push(record);
         }
 
-        wtr.flush()?;
+        let mut manifest =
+            WriterBuilder::new().from_path(split_file_path(output_file, "manifest"))?;
+        manifest.write_record(["文件", "记录数"])?;
+        for (key, group) in groups {
+            let split_file = split_file_path(output_file, &key);
+            write_records(&split_file, group.iter().copied(), template)?;
+            let file_name = split_file.file_name().unwrap_or_default().to_string_lossy();
+            manifest.write_record([file_name.as_ref(), &group.len().to_string()])?;
+        }
+        manifest.flush()?;
         Ok(())
     }
 }
+
+fn write_records<'a>(
+    output_file: &Path,
+    records: impl Iterator<Item = &'a OutputRecord>,
+    template: &Template,
+) -> DynResult<()> {
+    println!("写入输出文件: {}", output_file.display());
+    let mut wtr = WriterBuilder::new().from_writer(Vec::new());
+
+    wtr.write_record(template.headers())?;
+    for record in records {
+        wtr.write_record(template.format_record(record)?)?;
+    }
+
+    wtr.flush()?;
+    let content = String::from_utf8(wtr.into_inner()?)?;
+    std::fs::write(output_file, template.encode(&content))?;
+    Ok(())
+}
+
+// output.csv with key 2024-05 -> output-2024-05.csv
+fn split_file_path(output_file: &Path, key: &str) -> PathBuf {
+    let stem = output_file
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let extension = output_file
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let key = key.replace(['/', '\\'], "_");
+    let file_name = if extension.is_empty() {
+        format!("{}-{}", stem, key)
+    } else {
+        format!("{}-{}.{}", stem, key, extension)
+    };
+    output_file.with_file_name(file_name)
+}
@@ -0,0 +1,250 @@
+use chrono::{FixedOffset, NaiveDate, NaiveTime};
+use csv::ReaderBuilder;
+use encoding_rs::Encoding;
+use log::{debug, info, warn};
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use crate::arguments::{self, User};
+use crate::category;
+use crate::currency::normalize_currency;
+use crate::datetime::china_timezone;
+use crate::encoding::{find_column, open_input, require_column};
+use crate::{DynResult, OutputRecord};
+
+// column indexes of the activity export, resolved from the header row
+struct Header {
+    date: usize,
+    time: usize,
+    timezone: usize,
+    name: usize,
+    r#type: usize,
+    status: usize,
+    currency: usize,
+    gross: usize,
+    transaction_id: usize,
+    item_title: Option<usize>,
+}
+
+impl Header {
+    fn resolve(record: &csv::StringRecord) -> DynResult<Header> {
+        let find_optional = |name: &str| find_column(record, &[name]);
+        let find = |name: &str| require_column(record, &[name], "PayPal账单");
+        Ok(Header {
+            date: find("Date")?,
+            time: find("Time")?,
+            timezone: find("TimeZone")?,
+            name: find("Name")?,
+            r#type: find("Type")?,
+            status: find("Status")?,
+            currency: find("Currency")?,
+            gross: find("Gross")?,
+            transaction_id: find("Transaction ID")?,
+            item_title: find_optional("Item Title"),
+        })
+    }
+}
+
+// activity types moving money between PayPal and the funding source or
+// between currencies, the payment itself is recorded by another row
+const SKIPPED_TYPES: [&str; 5] = [
+    "General Currency Conversion",
+    "General Card Deposit",
+    "Bank Deposit to PP Account",
+    "General Authorization",
+    "Void of Authorization",
+];
+
+pub fn read_input_file(
+    input_file: &Path,
+    user: &User,
+    encoding: Option<&'static Encoding>,
+) -> DynResult<Vec<OutputRecord>> {
+    let decoder = open_input(input_file, encoding)?;
+
+    // create csv reader
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(decoder);
+
+    let mut records = Vec::new();
+    let mut header: Option<Header> = None;
+
+    for result in rdr.records() {
+        let record = result?;
+
+        // the first line is the header
+        let header = match &header {
+            Some(header) => header,
+            None => {
+                header = Some(Header::resolve(&record)?);
+                continue;
+            }
+        };
+
+        // get items
+        let source = "PayPal";
+        let get = |index: usize| record.get(index).unwrap_or("");
+        let transaction_time = format!("{} {}", get(header.date), get(header.time));
+        let description = get(header.r#type).to_string();
+        let counterparty = get(header.name).to_string();
+        let status = get(header.status);
+        let gross = get(header.gross)
+            .replace(',', "")
+            .parse::<f32>()
+            .map_err(|e| {
+                format!(
+                    "{} {}: 不支持的金额输入格式: {}",
+                    transaction_time, source, e
+                )
+            })?;
+        let amount = gross.abs();
+        let currency = normalize_currency(get(header.currency))
+            .map(|c| c.to_string())
+            .unwrap_or_else(|| get(header.currency).to_uppercase());
+        let transaction_id = get(header.transaction_id).to_string();
+        let item_title = header.item_title.map_or("", get).to_string();
+
+        // ignore some items
+        if status != "Completed" {
+            debug!(
+                "{} {}: 跳过未完成交易: {:?}",
+                transaction_time, source, record
+            );
+            continue;
+        }
+        if SKIPPED_TYPES.contains(&description.as_str()) {
+            debug!(
+                "{} {}: 跳过资金来源或换汇交易: {:?}",
+                transaction_time, source, record
+            );
+            continue;
+        }
+        if amount == 0.0 {
+            debug!(
+                "{} {}: 跳过金额为0的交易: {:?}",
+                transaction_time, source, record
+            );
+            continue;
+        }
+
+        let transaction_type = if description.contains("Refund") || description.contains("Reversal")
+        {
+            "退款".to_string()
+        } else if gross < 0.0 {
+            "支出".to_string()
+        } else {
+            "收入".to_string()
+        };
+
+        let account_from = match user {
+            User::Yang => "PayPal-杨".to_string(),
+            User::Han => "PayPal-韩".to_string(),
+        };
+
+        // category setting
+        let mut rule_hits = Vec::new();
+        let (category1, category2) = category::filter_category(
+            &counterparty,
+            &item_title,
+            &transaction_type,
+            amount,
+            &mut rule_hits,
+        );
+
+        // prepare remarks
+        let remark = if item_title.is_empty() {
+            description + ": " + &counterparty
+        } else {
+            item_title + ": " + &counterparty
+        };
+
+        // parse date-time, PayPal writes the date in US format
+        let date = NaiveDate::parse_from_str(get(header.date), "%m/%d/%Y")
+            .or_else(|_| NaiveDate::parse_from_str(get(header.date), "%Y-%m-%d"))
+            .map_err(|e| format!("{} {}: 日期格式不正确: {}", transaction_time, source, e))?;
+        let time = NaiveTime::parse_from_str(get(header.time), "%H:%M:%S")
+            .map_err(|e| format!("{} {}: 时间格式不正确: {}", transaction_time, source, e))?;
+        let timezone = parse_timezone(get(header.timezone)).unwrap_or_else(|| {
+            warn!(
+                "{} {}: 未知的时区: {}，按东八区处理",
+                transaction_time,
+                source,
+                get(header.timezone)
+            );
+            china_timezone()
+        });
+
+        let output_record = OutputRecord {
+            date: date.and_time(time),
+            timezone,
+            r#type: transaction_type,
+            amount,
+            category1,
+            category2,
+            account1: account_from,
+            account2: String::new(),
+            remark,
+            currency,
+            tag: String::new(),
+            source: String::from(source),
+            user: user.clone(),
+            counterparty,
+            transaction_id,
+            row: record.position().map_or(0, |p| p.line()),
+            raw: record.iter().map(|s| s.to_string()).collect(),
+            rule_hits,
+        };
+
+        records.push(output_record);
+    }
+
+    Ok(records)
+}
+
+// offsets written out, e.g. "GMT+8" or "UTC-03:30"
+static OFFSET_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(?:GMT|UTC)([+-])(\d{1,2})(?::?(\d{2}))?$").unwrap());
+
+// "GMT+08:00", "UTC-7" or a common abbreviation like "PDT"
+fn parse_timezone(timezone: &str) -> Option<FixedOffset> {
+    let hours = match timezone {
+        "PST" => -8,
+        "PDT" => -7,
+        "MST" => -7,
+        "MDT" => -6,
+        "EST" => -5,
+        "EDT" => -4,
+        "GMT" | "UTC" => 0,
+        "BST" | "CET" => 1,
+        "CEST" => 2,
+        "JST" | "KST" => 9,
+        "HKT" => 8,
+        _ => {
+            let c = OFFSET_RE.captures(timezone)?;
+            let sign = if &c[1] == "-" { -1 } else { 1 };
+            let hours: i32 = c[2].parse().ok()?;
+            let minutes: i32 = c.get(3).map_or(Some(0), |m| m.as_str().parse().ok())?;
+            return FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60));
+        }
+    };
+    FixedOffset::east_opt(hours * 3600)
+}
+
+pub fn handle_bill(
+    user: &arguments::User,
+    records: &mut Vec<OutputRecord>,
+    input_file: &PathBuf,
+    encoding: Option<&'static Encoding>,
+) {
+    let input_file = Path::new(input_file);
+    info!("处理账单文件: {}", input_file.display());
+    println!("处理PayPal账单: {}", input_file.display());
+    let current_records =
+        read_input_file(input_file, user, encoding).expect("read input csv file error");
+    println!("处理PayPal账单条目数量: {}", current_records.len());
+    records.extend(current_records);
+}
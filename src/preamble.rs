@@ -0,0 +1,91 @@
+use log::info;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+
+// summary written above the transaction list by the WeChat and Alipay exports:
+//   共6笔记录
+//   收入：2笔 250.00元
+//   支出：3笔 79.50元
+//   中性交易：1笔 100.00元
+// the older Alipay export writes it below the list instead:
+//   已收入:0笔,0.00元
+//   待收入:0笔,0.00元
+//   已支出:1笔,3.00元
+#[derive(Debug, Default)]
+pub struct Preamble {
+    count: Option<usize>,
+    // count and total amount by direction
+    totals: BTreeMap<String, (usize, f64)>,
+}
+
+static COUNT_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^共\s*(\d+)\s*笔记录").unwrap());
+static TOTAL_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\S+?)[：:]\s*(\d+)\s*笔[\s,，]*([\d,.]+)\s*元").unwrap());
+
+// count and total amount of the transaction rows by direction
+#[derive(Debug, Default)]
+pub struct Tally {
+    count: usize,
+    totals: BTreeMap<String, (usize, f64)>,
+}
+
+impl Preamble {
+    // read one preamble line, lines other than the summary are logged only
+    pub fn parse_line(&mut self, line: &str) {
+        let line = line.trim_end_matches(',').trim();
+        if line.is_empty() || line.starts_with('-') {
+            return;
+        }
+
+        if let Some(c) = COUNT_RE.captures(line) {
+            self.count = c[1].parse().ok();
+        } else if let Some(c) = TOTAL_RE.captures(line) {
+            // pending totals are not in the list, "已收入" is the "收入" of the rows
+            let direction = &c[1];
+            if direction.starts_with('待') {
+                info!("账单信息: {}", line);
+                return;
+            }
+            let direction = direction.strip_prefix('已').unwrap_or(direction);
+            let count = c[2].parse().unwrap_or(0);
+            let amount = c[3].replace(',', "").parse().unwrap_or(0.0);
+            self.totals.insert(direction.to_string(), (count, amount));
+        } else {
+            info!("账单信息: {}", line);
+        }
+    }
+}
+
+impl Tally {
+    // count a transaction row, before any row is skipped
+    pub fn add(&mut self, direction: &str, amount: f32) {
+        self.count += 1;
+        let total = self.totals.entry(direction.to_string()).or_default();
+        total.0 += 1;
+        total.1 += amount as f64;
+    }
+}
+
+// compare the preamble with the rows read, so that dropped or misread rows
+// do not go unnoticed
+pub fn verify(source: &str, preamble: &Preamble, tally: &Tally) {
+    if let Some(count) = preamble.count {
+        if count != tally.count {
+            println!(
+                "{}账单校验: 账单记录数为{}，实际读取{}条",
+                source, count, tally.count
+            );
+        }
+    }
+    for (direction, (count, amount)) in &preamble.totals {
+        let (actual_count, actual_amount) =
+            tally.totals.get(direction).copied().unwrap_or_default();
+        if *count != actual_count || (amount - actual_amount).abs() >= 0.005 {
+            println!(
+                "{}账单校验: {}为{}笔 {:.2}元，实际读取{}笔 {:.2}元",
+                source, direction, count, amount, actual_count, actual_amount
+            );
+        }
+    }
+}
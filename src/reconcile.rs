@@ -0,0 +1,123 @@
+use chrono::NaiveDate;
+use log::info;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::datetime::parse_date_time;
+use crate::encoding::open_csv_with_headers;
+use crate::{DynResult, OutputRecord};
+
+// header names used by the balances file, the first found is used
+const ACCOUNT_HEADERS: [&str; 2] = ["账户", "account"];
+const DATE_HEADERS: [&str; 2] = ["日期", "date"];
+const BALANCE_HEADERS: [&str; 2] = ["余额", "balance"];
+
+// balances taken from statements, by account and the day they were taken at
+// the end of. the first balance of an account is the opening balance, the
+// records after it are checked against the later ones
+pub struct Balances {
+    checkpoints: BTreeMap<String, BTreeMap<NaiveDate, f64>>,
+}
+
+impl Balances {
+    // read a csv of account, date and balance, e.g. "零钱-杨,2024-05-31,1024.50"
+    pub fn load(input_file: &Path) -> DynResult<Balances> {
+        let (mut rdr, find) = open_csv_with_headers(input_file, "余额文件")?;
+        let account_index = find(&ACCOUNT_HEADERS)?;
+        let date_index = find(&DATE_HEADERS)?;
+        let balance_index = find(&BALANCE_HEADERS)?;
+
+        let mut checkpoints: BTreeMap<String, BTreeMap<NaiveDate, f64>> = BTreeMap::new();
+        for result in rdr.records() {
+            let record = result?;
+            let get = |index: usize| record.get(index).unwrap_or("");
+            let account = get(account_index);
+            let date_str = get(date_index);
+            let date = parse_date_time(date_str)
+                .map_err(|e| format!("{} {}: {}", date_str, account, e))?
+                .date();
+            let balance = get(balance_index)
+                .replace([',', ' ', '¥', '￥'], "")
+                .parse::<f64>()
+                .map_err(|e| format!("{} {}: 不支持的余额格式: {}", date_str, account, e))?;
+            checkpoints
+                .entry(account.to_string())
+                .or_default()
+                .insert(date, balance);
+        }
+
+        Ok(Balances { checkpoints })
+    }
+
+    // replay the records of every account from its opening balance and report
+    // each account whose computed balance differs from a later statement,
+    // returns the number of mismatched accounts
+    pub fn reconcile(&self, records: &[OutputRecord]) -> usize {
+        let mut mismatched = 0;
+        for (account, checkpoints) in &self.checkpoints {
+            let mut checkpoints = checkpoints.iter();
+            let Some((opening_date, opening_balance)) = checkpoints.next() else {
+                continue;
+            };
+
+            let mut previous_date = *opening_date;
+            let mut balance = *opening_balance;
+            let mut diverged = false;
+            for (date, expected) in checkpoints {
+                let window: Vec<&OutputRecord> = records
+                    .iter()
+                    .filter(|r| {
+                        let day = r.date.date();
+                        day > previous_date && day <= *date
+                    })
+                    .collect();
+                balance += window
+                    .iter()
+                    .map(|r| balance_change(r, account))
+                    .sum::<f64>();
+
+                if (balance - expected).abs() >= 0.005 {
+                    println!(
+                        "对账 {}: {} 余额应为 {:.2}，计算为 {:.2}，差额 {:.2}，差异出现在 {} 至 {} 之间",
+                        account,
+                        date,
+                        expected,
+                        balance,
+                        expected - balance,
+                        previous_date.succ_opt().unwrap_or(previous_date),
+                        date
+                    );
+                    // transfers missing an account are the usual cause
+                    for record in window.iter().filter(|r| {
+                        r.r#type == "转账" && (r.account1 == "未知" || r.account2 == "未知")
+                    }) {
+                        println!(
+                            "    {} {}: 缺少账户的转账 {:.2}: {}",
+                            record.date, record.source, record.amount, record.remark
+                        );
+                    }
+                    diverged = true;
+                    break;
+                }
+                info!("对账 {}: {} 余额 {:.2} 一致", account, date, balance);
+                previous_date = *date;
+            }
+            if diverged {
+                mismatched += 1;
+            }
+        }
+        mismatched
+    }
+}
+
+// how the record moves the balance of the account
+fn balance_change(record: &OutputRecord, account: &str) -> f64 {
+    let amount = record.amount as f64;
+    match record.r#type.as_str() {
+        "支出" if record.account1 == account => -amount,
+        "收入" | "退款" if record.account1 == account => amount,
+        "转账" if record.account1 == account => -amount,
+        "转账" if record.account2 == account => amount,
+        _ => 0.0,
+    }
+}
@@ -0,0 +1,480 @@
+use chrono::{NaiveDateTime, TimeDelta};
+use csv::{ReaderBuilder, WriterBuilder};
+use log::info;
+use regex::Regex;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use crate::datetime::parse_date_time;
+use crate::encoding::open_input;
+use crate::icost::content_id;
+use crate::{DynResult, OutputRecord};
+
+// shares of a group expense are collected within this many days after paying
+const AA_WINDOW_DAYS: i64 = 7;
+
+const REIMBURSABLE_TAG: &str = "待报销";
+
+// fold the 群收款 shares friends paid back into the expense paid for the group,
+// so the expense is booked with the net share only. shares of the same
+// occasion paid close together are linked to the only earlier expense larger
+// than their sum that names the occasion. shares without such an expense are
+// left unchanged and printed to be linked by hand, returns the number of
+// linked shares
+pub fn link_aa_shares(records: &mut Vec<OutputRecord>) -> usize {
+    // shares by occasion, the remark is "群收款: <occasion>: <payer>"
+    let mut occasions: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (index, record) in records.iter().enumerate() {
+        if record.tag == "群收款" && record.r#type == "收入" {
+            let occasion = record
+                .remark
+                .rsplit_once(": ")
+                .map_or(record.remark.as_str(), |(occasion, _)| occasion);
+            occasions
+                .entry(occasion.to_string())
+                .or_default()
+                .push(index);
+        }
+    }
+
+    // recurring occasions like a weekly "午饭" are split where two shares are
+    // further apart than the window
+    let window = TimeDelta::days(AA_WINDOW_DAYS);
+    let mut groups: Vec<(String, Vec<usize>)> = Vec::new();
+    for (occasion, mut shares) in occasions {
+        shares.sort_by_key(|i| records[*i].date);
+        let mut group: Vec<usize> = Vec::new();
+        for share in shares {
+            if let Some(last) = group.last() {
+                if records[share].date - records[*last].date > window {
+                    groups.push((occasion.clone(), std::mem::take(&mut group)));
+                }
+            }
+            group.push(share);
+        }
+        groups.push((occasion, group));
+    }
+
+    let mut linked = HashSet::new();
+    for (occasion, shares) in groups {
+        let title = occasion.strip_prefix("群收款: ").unwrap_or(&occasion);
+        let first_share = shares.iter().map(|i| records[*i].date).min().unwrap();
+        let total = shares.iter().map(|i| records[*i].amount).sum::<f32>();
+        let candidates: Vec<usize> = records
+            .iter()
+            .enumerate()
+            .filter(|(_, record)| {
+                // an expense is shared by one group only
+                record.r#type == "支出"
+                    && record.tag != "AA"
+                    && record.amount > total
+                    && record.date <= first_share
+                    && first_share - record.date <= window
+                    && names_occasion(record, title)
+            })
+            .map(|(i, _)| i)
+            .collect();
+
+        let [index] = candidates[..] else {
+            let reason = if candidates.is_empty() {
+                "没有找到"
+            } else {
+                "找到多条"
+            };
+            println!(
+                "AA收款{}对应的支出，请手动关联: {} 合计 {:.2}",
+                reason, title, total
+            );
+            for i in &shares {
+                let share = &records[*i];
+                println!(
+                    "    {} {}: {} {:.2}",
+                    share.date, share.source, share.counterparty, share.amount
+                );
+            }
+            continue;
+        };
+        let payers = shares
+            .iter()
+            .map(|i| format!("{} {:.2}", records[*i].counterparty, records[*i].amount))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let expense = &mut records[index];
+        expense.remark = format!(
+            "{} (AA 总额 {:.2}，收回 {})",
+            expense.remark, expense.amount, payers
+        );
+        expense.amount = ((expense.amount - total) * 100.0).round() / 100.0;
+        expense.tag = "AA".to_string();
+        expense.rule_hits.push(format!("AA:{}", occasion));
+        linked.extend(shares);
+    }
+
+    let mut index = 0;
+    records.retain(|_| {
+        let keep = !linked.contains(&index);
+        index += 1;
+        keep
+    });
+    linked.len()
+}
+
+// the goods or the counterparty of the expense names the occasion, e.g. the
+// expense "火锅套餐: 海底捞" for the occasion "火锅"
+fn names_occasion(record: &OutputRecord, title: &str) -> bool {
+    if title.is_empty() || title == "/" {
+        return false;
+    }
+    let counterparty = record.counterparty.as_str();
+    record.remark.contains(title)
+        || (!counterparty.is_empty() && counterparty != "/" && title.contains(counterparty))
+}
+
+// tag expenses matching any of the patterns, matched against the counterparty
+// and the remark, returns the number of tagged records
+pub fn tag_reimbursable(records: &mut [OutputRecord], patterns: &[String]) -> DynResult<usize> {
+    let patterns = patterns
+        .iter()
+        .map(|p| Regex::new(p))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut count = 0;
+    for record in records.iter_mut() {
+        let text = format!("{} {}", record.counterparty, record.remark);
+        if record.r#type == "支出" && patterns.iter().any(|p| p.is_match(&text)) {
+            record.tag = REIMBURSABLE_TAG.to_string();
+            record.rule_hits.push("待报销".to_string());
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+// reimbursable expense, kept in the state file between runs. settled ones are
+// kept with the source and the transaction number of the reimbursement, so
+// that importing the same bills again does not reopen them
+#[derive(Debug)]
+struct Expense {
+    settled_by: String,
+    settled_source: String,
+    date: NaiveDateTime,
+    amount: f32,
+    account: String,
+    remark: String,
+    source: String,
+    transaction_id: String,
+}
+
+const STATE_HEADERS: [&str; 8] = [
+    "报销单号",
+    "日期",
+    "金额",
+    "账户",
+    "备注",
+    "来源",
+    "交易单号",
+    "报销来源",
+];
+
+// transaction number of the record, or its content for sources without one
+fn record_id(record: &OutputRecord) -> String {
+    if record.transaction_id.is_empty() {
+        content_id(record)
+    } else {
+        record.transaction_id.clone()
+    }
+}
+
+// reimbursements tracked across runs
+pub struct Reimbursements {
+    expenses: Vec<Expense>,
+}
+
+impl Reimbursements {
+    // read the state file, a missing file has nothing outstanding
+    pub fn load(state_file: &Path) -> DynResult<Reimbursements> {
+        let mut expenses = Vec::new();
+        if !state_file.exists() {
+            return Ok(Reimbursements { expenses });
+        }
+
+        let decoder = open_input(state_file, None)?;
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(true)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(decoder);
+        for result in rdr.records() {
+            let record = result?;
+            let get = |index: usize| record.get(index).unwrap_or("");
+            expenses.push(Expense {
+                settled_by: get(0).to_string(),
+                date: parse_date_time(get(1))?,
+                amount: get(2)
+                    .parse::<f32>()
+                    .map_err(|e| format!("{} 待报销: 不支持的金额输入格式: {}", get(1), e))?,
+                account: get(3).to_string(),
+                remark: get(4).to_string(),
+                source: get(5).to_string(),
+                transaction_id: get(6).to_string(),
+                settled_source: get(7).to_string(),
+            });
+        }
+        info!("读取报销记录: {} 条", expenses.len());
+
+        Ok(Reimbursements { expenses })
+    }
+
+    // add the new reimbursable expenses, and settle the outstanding ones paid
+    // back by an income mentioning "报销" with the same amount, oldest first.
+    // returns the number of expenses settled in this run
+    pub fn track(&mut self, records: &mut [OutputRecord]) -> usize {
+        for record in records.iter() {
+            if record.tag != REIMBURSABLE_TAG {
+                continue;
+            }
+            let id = record_id(record);
+            let known = self
+                .expenses
+                .iter()
+                .any(|o| o.source == record.source && o.transaction_id == id);
+            if !known {
+                self.expenses.push(Expense {
+                    settled_by: String::new(),
+                    settled_source: String::new(),
+                    date: record.date,
+                    amount: record.amount,
+                    account: record.account1.clone(),
+                    remark: record.remark.clone(),
+                    source: record.source.clone(),
+                    transaction_id: id,
+                });
+            }
+        }
+        self.expenses.sort_by_key(|o| o.date);
+
+        let mut count = 0;
+        for record in records.iter_mut() {
+            let text = format!("{} {}", record.counterparty, record.remark);
+            if record.r#type != "收入" || !text.contains("报销") {
+                continue;
+            }
+            // settled by the same income in an earlier run, or the oldest matching one
+            let id = record_id(record);
+            let settled = match self.expenses.iter().position(|o| {
+                !o.settled_by.is_empty() && o.settled_source == record.source && o.settled_by == id
+            }) {
+                Some(position) => &self.expenses[position],
+                None => {
+                    let Some(expense) = self.expenses.iter_mut().find(|o| {
+                        o.settled_by.is_empty()
+                            && o.date <= record.date
+                            && (o.amount - record.amount).abs() < 0.005
+                    }) else {
+                        continue;
+                    };
+                    expense.settled_by = id;
+                    expense.settled_source = record.source.clone();
+                    count += 1;
+                    expense
+                }
+            };
+            record.category1 = "报销".to_string();
+            record.category2 = String::new();
+            record.tag = "已报销".to_string();
+            record.remark = format!(
+                "{} (报销 {} {})",
+                record.remark,
+                settled.date.format("%Y-%m-%d"),
+                settled.remark
+            );
+            record
+                .rule_hits
+                .push(format!("报销:{}", settled.transaction_id));
+        }
+
+        // expenses already paid back are no longer waiting
+        for record in records.iter_mut() {
+            if record.tag != REIMBURSABLE_TAG {
+                continue;
+            }
+            let id = record_id(record);
+            let settled = self.expenses.iter().any(|o| {
+                !o.settled_by.is_empty() && o.source == record.source && o.transaction_id == id
+            });
+            if settled {
+                record.tag = "已报销".to_string();
+            }
+        }
+        count
+    }
+
+    // print what is still waiting for reimbursement
+    pub fn report(&self) {
+        let outstanding: Vec<&Expense> = self
+            .expenses
+            .iter()
+            .filter(|o| o.settled_by.is_empty())
+            .collect();
+        let total = outstanding.iter().map(|o| o.amount as f64).sum::<f64>();
+        println!("待报销记录数: {}，合计 {:.2}元", outstanding.len(), total);
+        for outstanding in outstanding {
+            println!(
+                "    {} {} {:.2}: {}",
+                outstanding.date, outstanding.account, outstanding.amount, outstanding.remark
+            );
+        }
+    }
+
+    pub fn save(&self, state_file: &Path) -> DynResult<()> {
+        let mut wtr = WriterBuilder::new().from_path(state_file)?;
+        wtr.write_record(STATE_HEADERS)?;
+        for o in &self.expenses {
+            wtr.write_record([
+                o.settled_by.clone(),
+                o.date.format("%Y-%m-%d %H:%M:%S").to_string(),
+                format!("{:.2}", o.amount),
+                o.account.clone(),
+                o.remark.clone(),
+                o.source.clone(),
+                o.transaction_id.clone(),
+                o.settled_source.clone(),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arguments::User;
+    use crate::datetime::china_timezone;
+
+    fn record(
+        date: &str,
+        r#type: &str,
+        amount: f32,
+        remark: &str,
+        counterparty: &str,
+    ) -> OutputRecord {
+        OutputRecord {
+            date: parse_date_time(date).unwrap(),
+            timezone: china_timezone(),
+            r#type: r#type.to_string(),
+            amount,
+            category1: String::new(),
+            category2: String::new(),
+            account1: "零钱-杨".to_string(),
+            account2: String::new(),
+            remark: remark.to_string(),
+            currency: "CNY".to_string(),
+            tag: String::new(),
+            source: "微信".to_string(),
+            user: User::Yang,
+            counterparty: counterparty.to_string(),
+            transaction_id: String::new(),
+            row: 0,
+            raw: Vec::new(),
+            rule_hits: Vec::new(),
+        }
+    }
+
+    fn share(date: &str, amount: f32, payer: &str) -> OutputRecord {
+        let mut share = record(
+            date,
+            "收入",
+            amount,
+            &format!("群收款: 火锅: {}", payer),
+            payer,
+        );
+        share.tag = "群收款".to_string();
+        share
+    }
+
+    #[test]
+    fn links_shares_to_the_expense_naming_the_occasion() {
+        let mut records = vec![
+            record(
+                "2024-05-01 19:00",
+                "支出",
+                300.0,
+                "火锅套餐: 海底捞",
+                "海底捞",
+            ),
+            record(
+                "2024-05-02 10:00",
+                "支出",
+                200.0,
+                "商户消费: 永辉超市",
+                "永辉超市",
+            ),
+            share("2024-05-03 09:00", 50.0, "李四"),
+            share("2024-05-03 09:10", 50.0, "王五"),
+        ];
+        assert_eq!(link_aa_shares(&mut records), 2);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].amount, 200.0);
+        assert_eq!(records[0].tag, "AA");
+        assert_eq!(records[1].amount, 200.0);
+        assert_eq!(records[1].tag, "");
+    }
+
+    #[test]
+    fn keeps_shares_without_a_matching_expense() {
+        let mut records = vec![
+            record(
+                "2024-05-01 19:00",
+                "支出",
+                300.0,
+                "商户消费: 某餐厅",
+                "某餐厅",
+            ),
+            share("2024-05-03 09:00", 50.0, "李四"),
+        ];
+        assert_eq!(link_aa_shares(&mut records), 0);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].amount, 300.0);
+    }
+
+    #[test]
+    fn keeps_shares_with_several_matching_expenses() {
+        let mut records = vec![
+            record("2024-05-01 19:00", "支出", 300.0, "火锅: 海底捞", "海底捞"),
+            record("2024-05-02 19:00", "支出", 280.0, "火锅: 小龙坎", "小龙坎"),
+            share("2024-05-03 09:00", 50.0, "李四"),
+        ];
+        assert_eq!(link_aa_shares(&mut records), 0);
+        assert_eq!(records.len(), 3);
+        assert!(records.iter().all(|r| r.tag != "AA"));
+    }
+
+    #[test]
+    fn tracks_expenses_without_transaction_number() {
+        let mut records = vec![
+            record("2024-05-01 09:00", "支出", 120.0, "出租车", ""),
+            record("2024-05-02 09:00", "支出", 80.0, "午饭", ""),
+            record("2024-05-10 09:00", "收入", 80.0, "差旅报销", ""),
+        ];
+        for record in records.iter_mut() {
+            record.source = "单位".to_string();
+        }
+        records[0].tag = REIMBURSABLE_TAG.to_string();
+        records[1].tag = REIMBURSABLE_TAG.to_string();
+
+        let mut reimbursements = Reimbursements {
+            expenses: Vec::new(),
+        };
+        assert_eq!(reimbursements.track(&mut records), 1);
+        assert_eq!(reimbursements.expenses.len(), 2);
+        assert_eq!(records[0].tag, REIMBURSABLE_TAG);
+        assert_eq!(records[1].tag, "已报销");
+        assert_eq!(records[2].tag, "已报销");
+
+        // importing the same bill again settles nothing new
+        records[1].tag = REIMBURSABLE_TAG.to_string();
+        assert_eq!(reimbursements.track(&mut records), 0);
+        assert_eq!(reimbursements.expenses.len(), 2);
+        assert_eq!(records[1].tag, "已报销");
+    }
+}
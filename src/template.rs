@@ -0,0 +1,252 @@
+use chrono::format::{Item, StrftimeItems};
+use encoding_rs::GBK;
+use log::{info, warn};
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::arguments::Profile;
+use crate::{DynResult, OutputRecord};
+
+// output schema, describes which columns are written and how values are formatted
+#[derive(Deserialize, Debug, Clone)]
+pub struct Template {
+    pub columns: Vec<Column>,
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
+    #[serde(default = "default_decimal_separator")]
+    pub decimal_separator: String,
+    // fixed number of decimal places, keep the shortest representation if not set
+    #[serde(default)]
+    pub decimal_places: Option<usize>,
+    #[serde(default)]
+    pub encoding: OutputEncoding,
+    // write transfer amounts as positive numbers in `signed_amount` column
+    #[serde(default)]
+    pub transfer_positive: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Column {
+    pub field: Field,
+    pub header: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Date,
+    Type,
+    Amount,
+    // negative for expense and transfer out, positive for income and refund
+    SignedAmount,
+    Category1,
+    Category2,
+    // category levels joined as "一级分类/二级分类"
+    Category,
+    Account1,
+    Account2,
+    // account money flows out of, empty for income and refund
+    SourceAccount,
+    // account money flows into, empty for expense
+    DestinationAccount,
+    // counterparty, or the target account for transfer
+    Payee,
+    Remark,
+    Currency,
+    Tag,
+    Source,
+    Counterparty,
+    TransactionId,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub enum OutputEncoding {
+    #[default]
+    #[serde(rename = "utf-8")]
+    Utf8,
+    #[serde(rename = "utf-8-bom")]
+    Utf8Bom,
+    #[serde(rename = "gbk")]
+    Gbk,
+}
+
+fn default_date_format() -> String {
+    "%Y年%m月%d日 %H:%M:%S".to_string()
+}
+
+fn default_decimal_separator() -> String {
+    ".".to_string()
+}
+
+impl Template {
+    pub fn load(template_file: &Path) -> DynResult<Template> {
+        info!("读取输出模板: {}", template_file.display());
+        let content = std::fs::read_to_string(template_file)?;
+        let template: Template = toml::from_str(&content)
+            .map_err(|e| format!("{}: 输出模板格式不正确: {}", template_file.display(), e))?;
+        if template.columns.is_empty() {
+            return Err(format!("{}: 输出模板没有定义任何列", template_file.display()).into());
+        }
+        // chrono panics when formatting with an invalid specifier
+        if StrftimeItems::new(&template.date_format).any(|item| item == Item::Error) {
+            return Err(format!(
+                "{}: 输出模板的日期格式不正确: {}",
+                template_file.display(),
+                template.date_format
+            )
+            .into());
+        }
+        Ok(template)
+    }
+
+    // built-in template of each output profile
+    pub fn from_profile(profile: &Profile) -> Template {
+        match profile {
+            Profile::ICost => Template::new(
+                &[
+                    (Field::Date, "日期"),
+                    (Field::Type, "类型"),
+                    (Field::Amount, "金额"),
+                    (Field::Category1, "一级分类"),
+                    (Field::Category2, "二级分类"),
+                    (Field::Account1, "账户1"),
+                    (Field::Account2, "账户2"),
+                    (Field::Remark, "备注"),
+                    (Field::Currency, "货币"),
+                    (Field::Tag, "标签"),
+                    (Field::Source, "来源"),
+                ],
+                &default_date_format(),
+                false,
+            ),
+            Profile::Firefly => Template::new(
+                &[
+                    (Field::Date, "Date"),
+                    (Field::Remark, "Description"),
+                    (Field::SignedAmount, "Amount"),
+                    (Field::Currency, "Currency code"),
+                    (Field::SourceAccount, "Source account"),
+                    (Field::DestinationAccount, "Destination account"),
+                    (Field::Category, "Category"),
+                    (Field::Tag, "Tags"),
+                    (Field::Source, "Notes"),
+                    (Field::TransactionId, "External ID"),
+                ],
+                "%Y-%m-%d %H:%M:%S",
+                true,
+            ),
+            Profile::Actual => Template::new(
+                &[
+                    (Field::Date, "Date"),
+                    (Field::Account1, "Account"),
+                    (Field::Payee, "Payee"),
+                    (Field::Remark, "Notes"),
+                    (Field::Category, "Category"),
+                    (Field::SignedAmount, "Amount"),
+                ],
+                "%Y-%m-%d",
+                false,
+            ),
+        }
+    }
+
+    fn new(columns: &[(Field, &str)], date_format: &str, transfer_positive: bool) -> Template {
+        Template {
+            columns: columns
+                .iter()
+                .map(|(field, header)| Column {
+                    field: *field,
+                    header: header.to_string(),
+                })
+                .collect(),
+            date_format: date_format.to_string(),
+            decimal_separator: default_decimal_separator(),
+            decimal_places: None,
+            encoding: OutputEncoding::Utf8,
+            transfer_positive,
+        }
+    }
+
+    pub fn headers(&self) -> Vec<&str> {
+        self.columns.iter().map(|c| c.header.as_str()).collect()
+    }
+
+    // format one record into the cells of a csv row
+    pub fn format_record(&self, record: &OutputRecord) -> DynResult<Vec<String>> {
+        let mut row = Vec::with_capacity(self.columns.len());
+        for column in &self.columns {
+            let cell = match column.field {
+                Field::Date => record.date_time().format(&self.date_format).to_string(),
+                Field::Type => record.r#type.clone(),
+                Field::Amount => self.format_amount(record.amount),
+                Field::SignedAmount => {
+                    if self.transfer_positive && record.r#type == "转账" {
+                        self.format_amount(record.amount)
+                    } else {
+                        self.format_amount(record.signed_amount())
+                    }
+                }
+                Field::Category1 => record.category1.clone(),
+                Field::Category2 => record.category2.clone(),
+                Field::Category => record.category(),
+                Field::Account1 => record.account1.clone(),
+                Field::Account2 => record.account2.clone(),
+                Field::SourceAccount => match record.r#type.as_str() {
+                    "收入" | "退款" => String::new(),
+                    _ => record.account1.clone(),
+                },
+                Field::DestinationAccount => match record.r#type.as_str() {
+                    "收入" | "退款" => record.account1.clone(),
+                    "转账" => record.account2.clone(),
+                    _ => String::new(),
+                },
+                Field::Payee => {
+                    if record.r#type == "转账" {
+                        record.account2.clone()
+                    } else {
+                        record.counterparty.clone()
+                    }
+                }
+                Field::Remark => record.remark.clone(),
+                Field::Currency => record.currency.clone(),
+                Field::Tag => record.tag.clone(),
+                Field::Source => record.source.clone(),
+                Field::Counterparty => record.counterparty.clone(),
+                Field::TransactionId => record.transaction_id.clone(),
+            };
+            row.push(cell);
+        }
+        Ok(row)
+    }
+
+    fn format_amount(&self, amount: f32) -> String {
+        let text = match self.decimal_places {
+            Some(places) => format!("{:.*}", places, amount),
+            None => format!("{:?}", amount),
+        };
+        if self.decimal_separator == "." {
+            text
+        } else {
+            text.replace('.', &self.decimal_separator)
+        }
+    }
+
+    // encode the whole csv content with the output encoding
+    pub fn encode(&self, content: &str) -> Vec<u8> {
+        match self.encoding {
+            OutputEncoding::Utf8 => content.as_bytes().to_vec(),
+            OutputEncoding::Utf8Bom => {
+                let mut bytes = vec![0xEF, 0xBB, 0xBF];
+                bytes.extend_from_slice(content.as_bytes());
+                bytes
+            }
+            OutputEncoding::Gbk => {
+                let (bytes, _, had_errors) = GBK.encode(content);
+                if had_errors {
+                    warn!("部分字符无法使用GBK编码，已替换为HTML实体");
+                }
+                bytes.into_owned()
+            }
+        }
+    }
+}
@@ -1,18 +1,23 @@
 use csv::ReaderBuilder;
-use encoding_rs::UTF_8;
-use encoding_rs_io::DecodeReaderBytesBuilder;
+use encoding_rs::Encoding;
 use log::{debug, info};
 use regex::Regex;
 use std::path::{Path, PathBuf};
 
 use crate::arguments::{self, User};
+use crate::category;
+use crate::currency::detect_currency;
+use crate::datetime::{china_timezone, parse_date_time};
+use crate::encoding::open_input;
+use crate::preamble::{self, Preamble, Tally};
 use crate::{DynResult, OutputRecord};
 
-pub fn read_input_file(input_file: &Path, user: &User) -> DynResult<Vec<OutputRecord>> {
-    let file = std::fs::File::open(input_file)?;
-    let decoder = DecodeReaderBytesBuilder::new()
-        .encoding(Some(UTF_8))
-        .build(file);
+pub fn read_input_file(
+    input_file: &Path,
+    user: &User,
+    encoding: Option<&'static Encoding>,
+) -> DynResult<Vec<OutputRecord>> {
+    let decoder = open_input(input_file, encoding)?;
 
     // create csv reader
     let mut rdr = ReaderBuilder::new()
@@ -23,18 +28,21 @@ pub fn read_input_file(input_file: &Path, user: &User) -> DynResult<Vec<OutputRe
 
     let mut records = Vec::new();
     let mut headers_found = false;
+    let mut preamble = Preamble::default();
+    let mut tally = Tally::default();
+    let re = Regex::new(r"^\D*").unwrap(); // delete all non-digit characters until the first digit
 
     for result in rdr.records() {
         let record = result?;
 
         // find the first normal line in csv records
         if !headers_found {
-            if record.get(0).map_or(false, |s| s.contains("交易时间")) {
+            if record.get(0).is_some_and(|s| s.contains("交易时间")) {
                 headers_found = true;
-                continue;
             } else {
-                continue;
+                preamble.parse_line(&record.iter().collect::<Vec<_>>().join(","));
             }
+            continue;
         }
 
         // get items
@@ -46,7 +54,6 @@ pub fn read_input_file(input_file: &Path, user: &User) -> DynResult<Vec<OutputRe
         let mut remark = record.get(3).unwrap_or("").to_string();
         let amount_str = record.get(5).unwrap_or("");
 
-        let re = Regex::new(r"^\D*").unwrap(); // delete all non-digit characters until the first digit
         let amount = re
             .replace(amount_str, "")
             .to_string()
@@ -58,15 +65,20 @@ pub fn read_input_file(input_file: &Path, user: &User) -> DynResult<Vec<OutputRe
                     transaction_time, source, e
                 )
             })?;
+        // the preamble calls the "/" direction 中性交易
+        match transaction_direction.as_str() {
+            "/" => tally.add("中性交易", amount),
+            direction => tally.add(direction, amount),
+        }
         // used for income/outcome account and the source account of transfer
         let mut account_from = record.get(6).unwrap_or("").to_string();
         // only used for transfer item, as the target account
         let mut account_to = String::from("");
+        let mut tag = String::new();
         let status = record.get(7).unwrap_or("").to_string();
-        let currency = match amount_str.chars().next() {
-            Some('¥') => "CNY".to_string(),
-            _ => "".to_string(),
-        };
+        let transaction_id = record.get(8).unwrap_or("").to_string();
+        // amounts are CNY unless marked with another currency
+        let currency = detect_currency(amount_str).unwrap_or("CNY").to_string();
 
         // ignore some items
         if status == "已全额退款" {
@@ -77,11 +89,18 @@ pub fn read_input_file(input_file: &Path, user: &User) -> DynResult<Vec<OutputRe
             continue;
         }
 
+        // purchases paid by 零钱通
+        if account_from == "零钱通" {
+            account_from = "微信零钱通".to_string();
+        }
+
         // handle special items
-        if transaction_direction == "/" && transaction_type.contains("转入零钱通") {
-            transaction_direction = "转账".to_string();
-            account_from = "零钱".to_string();
-            account_to = "微信零钱通".to_string();
+        if let Some((flow_type, flow_from, flow_to)) =
+            lingqiantong_flow(&transaction_type, &account_from)
+        {
+            transaction_direction = flow_type.to_string();
+            account_from = flow_from;
+            account_to = flow_to;
             remark = transaction_type.clone();
         }
 
@@ -90,22 +109,50 @@ pub fn read_input_file(input_file: &Path, user: &User) -> DynResult<Vec<OutputRe
             account_from = "零钱".to_string();
         }
 
+        // red packets are gifts, those between family members become transfers later
+        let mut category_fixed = None;
+        if transaction_type.starts_with("微信红包") {
+            if transaction_type.contains("退款") {
+                transaction_direction = "退款".to_string();
+            }
+            remark = transaction_type.clone();
+            category_fixed = Some(("人情".to_string(), "红包".to_string()));
+        } else if transaction_type == "群收款" {
+            // keep who collected or paid, the goods name is the occasion
+            remark = format!("群收款: {}", remark);
+            tag = "群收款".to_string();
+        }
+
         // append user to account
         account_from = append_user_postfix(&account_from, user);
         account_to = append_user_postfix(&account_to, user);
 
         // category setting
-        let (category1, category2) =
-            filter_category(&counterparty, &remark, &transaction_direction);
+        let mut rule_hits = Vec::new();
+        let (category1, category2) = match category_fixed {
+            Some(category) => {
+                rule_hits.push("交易类型:微信红包".to_string());
+                category
+            }
+            None => category::filter_category(
+                &counterparty,
+                &remark,
+                &transaction_direction,
+                amount,
+                &mut rule_hits,
+            ),
+        };
 
         // prepare remarks
         let remark = remark + ": " + &counterparty;
 
-        // format date-time
-        let formatted_date = format_date(&transaction_time);
+        // parse date-time
+        let date = parse_date_time(&transaction_time)
+            .map_err(|e| format!("{} {}: {}", transaction_time, source, e))?;
 
         let output_record = OutputRecord {
-            date: formatted_date,
+            date,
+            timezone: china_timezone(),
             r#type: transaction_direction,
             amount,
             category1,
@@ -114,79 +161,53 @@ pub fn read_input_file(input_file: &Path, user: &User) -> DynResult<Vec<OutputRe
             account2: account_to,
             remark,
             currency,
-            tag: String::new(),
+            tag,
             source: String::from(source),
+            user: user.clone(),
+            counterparty,
+            transaction_id,
+            row: record.position().map_or(0, |p| p.line()),
+            raw: record.iter().map(|s| s.to_string()).collect(),
+            rule_hits,
         };
 
         records.push(output_record);
     }
 
-    Ok(records)
-}
+    preamble::verify("微信", &preamble, &tally);
 
-// format date-time string
-// input：year-month-day hour:minute:second
-// output：year年month月day日 hour:minute:second
-fn format_date(input: &str) -> String {
-    let parts: Vec<&str> = input.split_whitespace().collect();
-    assert!(parts.len() == 2, "日期时间格式不正确: {}", input,);
-
-    let date_part = parts[0];
-    let time_part = parts[1];
-
-    let date_components: Vec<&str> = date_part.split('-').collect();
-    assert!(date_components.len() == 3, "日期格式不正确: {}", date_part,);
-
-    let year = date_components[0];
-    let month = date_components[1].to_string();
-    let day = date_components[2].to_string();
-
-    let time_components: Vec<&str> = time_part.split(':').collect();
-    assert!(time_components.len() == 3, "时间格式不正确: {}", time_part,);
-
-    let hour = time_components[0].to_string();
-    let minute = time_components[1].to_string();
-    let second = time_components[2].to_string();
-
-    format!(
-        "{}年{}月{}日 {}:{}:{}",
-        year, month, day, hour, minute, second
-    )
+    Ok(records)
 }
 
-fn filter_category(
-    counterparty: &str,
-    remark: &str,
-    transaction_direction: &str,
-) -> (String, String) {
-    if transaction_direction == "转账" {
-        return ("".to_string(), "".to_string());
+// 零钱通 movements: yields are income of 零钱通, transfers in and out are
+// transfers between 零钱通 and 零钱 or a bank card
+fn lingqiantong_flow(
+    transaction_type: &str,
+    method: &str,
+) -> Option<(&'static str, String, String)> {
+    if !transaction_type.contains("零钱通") {
+        return None;
     }
-
-    let mut category1 = "未知".to_string();
-    let mut category2 = "".to_string();
-    if counterparty.contains("禹泉水处理设备") {
-        category1 = "账单".to_string();
-        category2 = "水费".to_string();
-    } else if counterparty.contains("北京市顺义区妇幼保健院") {
-        category1 = "医疗".to_string();
-        category2 = "门诊".to_string();
-    } else if counterparty.contains("易寄件") {
-        category1 = "杂项".to_string();
-        category2 = "快递费".to_string();
-    } else if counterparty.contains("顺义鑫绿都生活超市后沙峪店")
-        || counterparty.contains("永辉超市")
-    {
-        category1 = "食材".to_string();
-        category2 = "蔬菜".to_string();
-    }
-
-    if remark.contains("霸王茶姬") {
-        category1 = "餐饮".to_string();
-        category2 = "饮料".to_string();
+    if transaction_type.contains("收益") {
+        Some(("收入", "微信零钱通".to_string(), String::new()))
+    } else if let Some(from) = transaction_type.strip_prefix("转入零钱通-来自") {
+        let from = match from {
+            "零钱" => "零钱",
+            // the card is named in the payment method as well
+            _ if !method.is_empty() && method != "/" => method,
+            _ => "未知",
+        };
+        Some(("转账", from.to_string(), "微信零钱通".to_string()))
+    } else if let Some(to) = transaction_type.strip_prefix("零钱通转出-到") {
+        let to = match to {
+            "零钱" => "零钱",
+            // the card is resolved from the remark later
+            _ => "未知",
+        };
+        Some(("转账", "微信零钱通".to_string(), to.to_string()))
+    } else {
+        None
     }
-
-    (category1, category2)
 }
 
 fn append_user_postfix(account: &str, user: &User) -> String {
@@ -200,11 +221,17 @@ fn append_user_postfix(account: &str, user: &User) -> String {
     }
 }
 
-pub fn handle_bill(user: &arguments::User, records: &mut Vec<OutputRecord>, input_file: &PathBuf) {
+pub fn handle_bill(
+    user: &arguments::User,
+    records: &mut Vec<OutputRecord>,
+    input_file: &PathBuf,
+    encoding: Option<&'static Encoding>,
+) {
     let input_file = Path::new(input_file);
     info!("处理账单文件: {}", input_file.display());
     println!("处理微信账单: {}", input_file.display());
-    let current_records = read_input_file(input_file, user).expect("read input csv file error");
+    let current_records =
+        read_input_file(input_file, user, encoding).expect("read input csv file error");
     println!("处理微信账单条目数量: {}", current_records.len());
     records.extend(current_records);
 }
@@ -0,0 +1,161 @@
+use chrono::{NaiveDate, NaiveTime};
+use std::collections::BTreeMap;
+
+use crate::arguments::Period;
+use crate::OutputRecord;
+
+// daily yields of money-market funds, e.g. "余额宝-2024.05.05-收益发放"
+fn is_yield(record: &OutputRecord) -> bool {
+    record.r#type == "收入"
+        && record.category1 == "资本"
+        && record.category2 == "投资收入"
+        && record.remark.contains("收益")
+}
+
+// replace the yields of each account with one 收入 record per period, dated
+// at the end of the period. a period cut by the date window is clamped to it,
+// the yields outside the window are expected to be dropped already. returns
+// the number of folded daily records
+pub fn aggregate_yields(
+    records: &mut Vec<OutputRecord>,
+    period: &Period,
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+) -> usize {
+    let (yields, rest): (Vec<_>, Vec<_>) = std::mem::take(records).into_iter().partition(is_yield);
+    *records = rest;
+
+    let mut groups: BTreeMap<_, Vec<OutputRecord>> = BTreeMap::new();
+    for record in yields {
+        let key = (
+            record.source.clone(),
+            record.account1.clone(),
+            period.last_day(record.date.date()),
+        );
+        groups.entry(key).or_default().push(record);
+    }
+
+    let mut count = 0;
+    for ((source, account, last_day), group) in groups {
+        count += group.len();
+        let first_day = period
+            .first_day(last_day)
+            .max(since.unwrap_or(NaiveDate::MIN));
+        let end_day = last_day.min(until.unwrap_or(NaiveDate::MAX));
+        let total = group.iter().map(|r| r.amount as f64).sum::<f64>();
+        let total = ((total * 100.0).round() / 100.0) as f32;
+        let first = &group[0];
+
+        records.push(OutputRecord {
+            date: end_day.and_time(NaiveTime::from_hms_opt(23, 59, 59).unwrap()),
+            timezone: first.timezone,
+            r#type: first.r#type.clone(),
+            amount: total,
+            category1: first.category1.clone(),
+            category2: first.category2.clone(),
+            account1: account.clone(),
+            account2: String::new(),
+            remark: format!(
+                "收益汇总: {} 至 {} 共{}笔 {:.2}元",
+                first_day,
+                end_day,
+                group.len(),
+                total
+            ),
+            currency: first.currency.clone(),
+            tag: first.tag.clone(),
+            source: source.clone(),
+            user: first.user.clone(),
+            counterparty: first.counterparty.clone(),
+            transaction_id: format!("收益汇总|{}|{}", account, last_day),
+            row: 0,
+            raw: Vec::new(),
+            rule_hits: vec![format!("收益汇总:{}", period.name())],
+        });
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arguments::User;
+    use crate::datetime::{china_timezone, parse_date_time};
+
+    fn daily_yield(day: u32, amount: f32) -> OutputRecord {
+        OutputRecord {
+            date: parse_date_time(&format!("2024-05-{:02} 04:00:00", day)).unwrap(),
+            timezone: china_timezone(),
+            r#type: "收入".to_string(),
+            amount,
+            category1: "资本".to_string(),
+            category2: "投资收入".to_string(),
+            account1: "余额宝-杨".to_string(),
+            account2: String::new(),
+            remark: format!("余额宝-2024.05.{:02}-收益发放: 天弘基金", day - 1),
+            currency: "CNY".to_string(),
+            tag: String::new(),
+            source: "支付宝".to_string(),
+            user: User::Yang,
+            counterparty: "天弘基金".to_string(),
+            transaction_id: format!("yield{}", day),
+            row: 0,
+            raw: Vec::new(),
+            rule_hits: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn folds_a_week_dated_at_its_end() {
+        // 2024-05-06 is a monday
+        let mut records: Vec<_> = (6..=12).map(|day| daily_yield(day, 0.1)).collect();
+        assert_eq!(aggregate_yields(&mut records, &Period::Week, None, None), 7);
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].date,
+            parse_date_time("2024-05-12 23:59:59").unwrap()
+        );
+        assert_eq!(records[0].amount, 0.7);
+        assert_eq!(
+            records[0].remark,
+            "收益汇总: 2024-05-06 至 2024-05-12 共7笔 0.70元"
+        );
+    }
+
+    #[test]
+    fn clamps_the_period_to_the_date_window() {
+        let mut records: Vec<_> = (8..=10).map(|day| daily_yield(day, 0.1)).collect();
+        let since = NaiveDate::from_ymd_opt(2024, 5, 8);
+        let until = NaiveDate::from_ymd_opt(2024, 5, 10);
+        assert_eq!(
+            aggregate_yields(&mut records, &Period::Week, since, until),
+            3
+        );
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].date,
+            parse_date_time("2024-05-10 23:59:59").unwrap()
+        );
+        assert_eq!(
+            records[0].remark,
+            "收益汇总: 2024-05-08 至 2024-05-10 共3笔 0.30元"
+        );
+    }
+
+    #[test]
+    fn keeps_other_records() {
+        let mut other = daily_yield(6, 25.0);
+        other.category1 = "餐饮".to_string();
+        let mut records = vec![other, daily_yield(6, 0.1)];
+        assert_eq!(
+            aggregate_yields(&mut records, &Period::Month, None, None),
+            1
+        );
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].category1, "餐饮");
+        assert_eq!(
+            records[1].remark,
+            "收益汇总: 2024-05-01 至 2024-05-31 共1笔 0.10元"
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use csv::ReaderBuilder;
+use encoding_rs::Encoding;
+use log::{debug, info};
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+use crate::arguments::{self, User};
+use crate::category;
+use crate::currency::detect_currency;
+use crate::datetime::{china_timezone, parse_date_time};
+use crate::encoding::{find_column, open_input, require_column};
+use crate::{DynResult, OutputRecord};
+
+// column indexes of the export, resolved from the header row
+struct Header {
+    time: usize,
+    r#type: usize,
+    merchant: usize,
+    amount: usize,
+    card: usize,
+    status: usize,
+    transaction_id: Option<usize>,
+    remark: Option<usize>,
+}
+
+impl Header {
+    fn resolve(record: &csv::StringRecord) -> DynResult<Header> {
+        let find_optional = |names: &[&str]| find_column(record, names);
+        let find = |names: &[&str]| require_column(record, names, "云闪付账单");
+        Ok(Header {
+            time: find(&["交易时间"])?,
+            r#type: find(&["交易类型"])?,
+            merchant: find(&["商户名称", "交易对方"])?,
+            amount: find(&["交易金额", "金额"])?,
+            card: find(&["付款方式", "付款卡", "交易卡号"])?,
+            status: find(&["交易状态"])?,
+            transaction_id: find_optional(&["订单编号", "订单号", "交易单号"]),
+            remark: find_optional(&["备注"]),
+        })
+    }
+}
+
+pub fn read_input_file(
+    input_file: &Path,
+    user: &User,
+    encoding: Option<&'static Encoding>,
+) -> DynResult<Vec<OutputRecord>> {
+    let decoder = open_input(input_file, encoding)?;
+
+    // create csv reader
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(decoder);
+
+    let mut records = Vec::new();
+    let mut header: Option<Header> = None;
+    // "工商银行 储蓄卡 尾号5678" -> 工商银行储蓄卡(5678)
+    let card_re =
+        Regex::new(r"^(\S+?)\s*(储蓄卡|借记卡|信用卡)\s*(?:尾号)?[(（]?(\d{4})[)）]?$").unwrap();
+
+    for result in rdr.records() {
+        let record = result?;
+
+        // find the first normal line in csv records
+        let header = match &header {
+            Some(header) => header,
+            None => {
+                if record.get(0).is_some_and(|s| s.contains("交易时间")) {
+                    header = Some(Header::resolve(&record)?);
+                }
+                continue;
+            }
+        };
+
+        // get items
+        let source = "云闪付";
+        let get = |index: usize| record.get(index).unwrap_or("");
+        let transaction_time = get(header.time).to_string();
+        let description = get(header.r#type).to_string();
+        let counterparty = get(header.merchant).to_string();
+        let amount_str = get(header.amount);
+        // the sign gives the direction when there is one, "-35.20" or "+5.00"
+        let unsigned = amount_str.replace(['¥', '￥', ',', ' '], "");
+        let sign = unsigned.chars().next().filter(|c| *c == '-' || *c == '+');
+        let amount = unsigned
+            .trim_start_matches(['-', '+'])
+            .parse::<f32>()
+            .map_err(|e| {
+                format!(
+                    "{} {}: 不支持的金额输入格式: {}",
+                    transaction_time, source, e
+                )
+            })?;
+        let card = get(header.card);
+        // used for income/outcome account and the source account of transfer
+        let mut account_from = match card_re.captures(card) {
+            Some(c) => format!("{}{}({})", &c[1], &c[2], &c[3]),
+            None => card.to_string(),
+        };
+        // only used for transfer item, as the target account
+        let mut account_to = String::from("");
+        let status = get(header.status).to_string();
+        let transaction_id = header.transaction_id.map_or("", get).to_string();
+        let remark = header.remark.map_or("", get).to_string();
+
+        // ignore some items
+        if status.contains("关闭") || status.contains("失败") || status.contains("撤销") {
+            debug!(
+                "{} {}: 跳过已关闭交易: {:?}",
+                transaction_time, source, record
+            );
+            continue;
+        }
+
+        // handle special items, same semantics as alipay
+        let transaction_type = if status.contains("退款") || description.contains("退款") {
+            "退款".to_string()
+        } else if description.contains("信用卡还款") {
+            // resolved from the counterparty and remark later
+            account_to = "未知".to_string();
+            "转账".to_string()
+        } else if let Some(sign) = sign {
+            if sign == '+' {
+                "收入".to_string()
+            } else {
+                "支出".to_string()
+            }
+        } else if description.contains("收款") || description.contains("转入") {
+            // unsigned amounts, the direction is guessed from the transaction type
+            "收入".to_string()
+        } else {
+            "支出".to_string()
+        };
+
+        if amount == 0.0 {
+            debug!(
+                "{} {}: 跳过金额为0的交易: {:?}",
+                transaction_time, source, record
+            );
+            continue;
+        }
+
+        // income without a card goes into the 云闪付 balance
+        if account_from.is_empty() || account_from == "/" {
+            account_from = "云闪付余额".to_string();
+        }
+
+        // category setting
+        let mut rule_hits = Vec::new();
+        let (category1, category2) = category::filter_category(
+            &counterparty,
+            &remark,
+            &transaction_type,
+            amount,
+            &mut rule_hits,
+        );
+
+        // prepare remarks
+        let remark = description + ": " + &counterparty;
+
+        // parse date-time
+        let date = parse_date_time(&transaction_time)
+            .map_err(|e| format!("{} {}: {}", transaction_time, source, e))?;
+
+        let output_record = OutputRecord {
+            date,
+            timezone: china_timezone(),
+            r#type: transaction_type,
+            amount,
+            category1,
+            category2,
+            account1: account_from,
+            account2: account_to,
+            remark,
+            currency: detect_currency(amount_str).unwrap_or("CNY").to_string(),
+            tag: String::new(),
+            source: String::from(source),
+            user: user.clone(),
+            counterparty,
+            transaction_id,
+            row: record.position().map_or(0, |p| p.line()),
+            raw: record.iter().map(|s| s.to_string()).collect(),
+            rule_hits,
+        };
+
+        records.push(output_record);
+    }
+
+    Ok(records)
+}
+
+pub fn handle_bill(
+    user: &arguments::User,
+    records: &mut Vec<OutputRecord>,
+    input_file: &PathBuf,
+    encoding: Option<&'static Encoding>,
+) {
+    let input_file = Path::new(input_file);
+    info!("处理账单文件: {}", input_file.display());
+    println!("处理云闪付账单: {}", input_file.display());
+    let current_records =
+        read_input_file(input_file, user, encoding).expect("read input csv file error");
+    println!("处理云闪付账单条目数量: {}", current_records.len());
+    records.extend(current_records);
+}
@@ -0,0 +1,239 @@
+use calamine::{open_workbook_auto, Data, DataType, Reader};
+use csv::ReaderBuilder;
+use encoding_rs::Encoding;
+use log::{debug, info, warn};
+use std::path::{Path, PathBuf};
+
+use crate::arguments::{self, User};
+use crate::category;
+use crate::datetime::{china_timezone, parse_date_time};
+use crate::encoding::open_input;
+use crate::{DynResult, OutputRecord};
+
+// China Merchants Bank credit card statement, exported as csv or xls
+pub fn read_input_file(
+    input_file: &Path,
+    user: &User,
+    encoding: Option<&'static Encoding>,
+) -> DynResult<Vec<OutputRecord>> {
+    let rows = match input_file.extension().and_then(|e| e.to_str()) {
+        Some("xls") | Some("xlsx") => read_sheet_rows(input_file)?,
+        _ => read_csv_rows(input_file, encoding)?,
+    };
+
+    let mut records = Vec::new();
+    let mut header: Option<Header> = None;
+
+    for (line, row) in rows.iter().enumerate() {
+        // find the first normal line in statement rows
+        let header = match &header {
+            Some(header) => header,
+            None => {
+                if row.first().is_some_and(|s| s.contains("交易日")) {
+                    header = Some(Header::resolve(row)?);
+                }
+                continue;
+            }
+        };
+
+        // get items
+        let source = "招商银行";
+        let get = |index: usize| row.get(index).map_or("", |s| s.as_str());
+        let transaction_time = get(header.transaction_date).to_string();
+        if transaction_time.is_empty() {
+            continue;
+        }
+        let posting_date = get(header.posting_date).to_string();
+        let summary = get(header.summary).to_string();
+        let card_tail = get(header.card_tail).to_string();
+        let amount_str = get(header.amount);
+        let signed_amount = amount_str
+            .replace(['¥', ',', ' '], "")
+            .parse::<f32>()
+            .map_err(|e| {
+                format!(
+                    "{} {}: 不支持的金额输入格式: {}",
+                    transaction_time, source, e
+                )
+            })?;
+        let amount = signed_amount.abs();
+
+        // the card itself is the account of every statement row
+        let card_account = format!("招商银行信用卡({})", card_tail);
+        let mut account_from = card_account.clone();
+        let mut account_to = String::from("");
+        let transaction_type;
+        let mut category1 = String::new();
+        let mut category2 = String::new();
+        let mut rule_hits = Vec::new();
+
+        // handle special items
+        if summary.contains("分期") && summary.contains("本金") {
+            debug!(
+                "{} {}: 跳过分期本金，原消费已记账: {:?}",
+                transaction_time, source, row
+            );
+            continue;
+        } else if summary.contains("分期") && summary.contains("手续费") {
+            transaction_type = "支出".to_string();
+            category1 = "金融".to_string();
+            category2 = "分期手续费".to_string();
+            rule_hits.push("交易摘要:分期手续费".to_string());
+        } else if summary.contains("利息") || summary.contains("年费") {
+            transaction_type = "支出".to_string();
+            category1 = "金融".to_string();
+            category2 = if summary.contains("利息") {
+                "利息".to_string()
+            } else {
+                "年费".to_string()
+            };
+            rule_hits.push(format!("交易摘要:{}", category2));
+        } else if summary.contains("还款") {
+            // repayment moves money from an unknown account into the card
+            transaction_type = "转账".to_string();
+            account_from = "未知".to_string();
+            account_to = card_account.clone();
+            warn!(
+                "{} {}: 需要手动添加还款来源账户: {:?}",
+                transaction_time, source, row
+            );
+        } else if signed_amount < 0.0 {
+            transaction_type = "退款".to_string();
+        } else {
+            transaction_type = "支出".to_string();
+        }
+
+        if amount == 0.0 {
+            debug!(
+                "{} {}: 跳过金额为0的交易: {:?}",
+                transaction_time, source, row
+            );
+            continue;
+        }
+
+        // category setting
+        if rule_hits.is_empty() {
+            (category1, category2) = category::filter_category(
+                &summary,
+                &summary,
+                &transaction_type,
+                amount,
+                &mut rule_hits,
+            );
+        }
+
+        // parse date-time
+        let date = parse_date_time(&transaction_time)
+            .map_err(|e| format!("{} {}: {}", transaction_time, source, e))?;
+
+        let output_record = OutputRecord {
+            date,
+            timezone: china_timezone(),
+            r#type: transaction_type,
+            amount,
+            category1,
+            category2,
+            account1: account_from,
+            account2: account_to,
+            remark: summary.clone(),
+            currency: "CNY".to_string(),
+            tag: String::new(),
+            source: String::from(source),
+            user: user.clone(),
+            counterparty: summary.clone(),
+            // statements have no transaction number, identify rows by their content
+            transaction_id: format!(
+                "{}|{}|{}|{}|{}",
+                transaction_time, posting_date, card_tail, amount_str, summary
+            ),
+            row: line as u64 + 1,
+            raw: row.clone(),
+            rule_hits,
+        };
+
+        records.push(output_record);
+    }
+
+    Ok(records)
+}
+
+// column indexes of the statement, resolved from the header row
+struct Header {
+    transaction_date: usize,
+    posting_date: usize,
+    summary: usize,
+    amount: usize,
+    card_tail: usize,
+}
+
+impl Header {
+    fn resolve(row: &[String]) -> DynResult<Header> {
+        let find = |name: &str| {
+            row.iter()
+                .position(|s| s.contains(name))
+                .ok_or_else(|| format!("招商银行账单缺少列: {}", name))
+        };
+        Ok(Header {
+            transaction_date: find("交易日")?,
+            posting_date: find("记账日")?,
+            summary: find("交易摘要")?,
+            amount: find("人民币金额")?,
+            card_tail: find("卡号末四位")?,
+        })
+    }
+}
+
+fn read_csv_rows(
+    input_file: &Path,
+    encoding: Option<&'static Encoding>,
+) -> DynResult<Vec<Vec<String>>> {
+    let decoder = open_input(input_file, encoding)?;
+
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(decoder);
+
+    let mut rows = Vec::new();
+    for result in rdr.records() {
+        let record = result?;
+        rows.push(record.iter().map(|s| s.to_string()).collect());
+    }
+    Ok(rows)
+}
+
+fn read_sheet_rows(input_file: &Path) -> DynResult<Vec<Vec<String>>> {
+    let mut workbook = open_workbook_auto(input_file)?;
+    let range = workbook
+        .worksheet_range_at(0)
+        .ok_or("招商银行账单没有工作表")??;
+
+    let rows = range
+        .rows()
+        .map(|row| row.iter().map(cell_to_string).collect())
+        .collect();
+    Ok(rows)
+}
+
+fn cell_to_string(cell: &Data) -> String {
+    match cell.as_datetime() {
+        Some(date_time) if cell.is_datetime() => date_time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        _ => cell.to_string().trim().to_string(),
+    }
+}
+
+pub fn handle_bill(
+    user: &arguments::User,
+    records: &mut Vec<OutputRecord>,
+    input_file: &PathBuf,
+    encoding: Option<&'static Encoding>,
+) {
+    let input_file = Path::new(input_file);
+    info!("处理账单文件: {}", input_file.display());
+    println!("处理招商银行信用卡账单: {}", input_file.display());
+    let current_records =
+        read_input_file(input_file, user, encoding).expect("read input bill file error");
+    println!("处理招商银行信用卡账单条目数量: {}", current_records.len());
+    records.extend(current_records);
+}
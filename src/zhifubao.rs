@@ -1,70 +1,169 @@
 use csv::ReaderBuilder;
-use encoding_rs::GBK;
-use encoding_rs_io::DecodeReaderBytesBuilder;
-use log::{debug, info, warn};
+use encoding_rs::Encoding;
+use log::{debug, info};
 use std::path::{Path, PathBuf};
 
 use crate::arguments::{self, User};
+use crate::category;
+use crate::currency::detect_currency;
+use crate::datetime::{china_timezone, parse_date_time};
+use crate::encoding::{find_column, open_input, require_column};
+use crate::preamble::{self, Preamble, Tally};
 use crate::{DynResult, OutputRecord};
 
-pub fn read_input_file(input_file: &Path, user: &User) -> DynResult<Vec<OutputRecord>> {
-    let file = std::fs::File::open(input_file)?;
-    let decoder = DecodeReaderBytesBuilder::new()
-        .encoding(Some(GBK))
-        .build(file);
+// column indexes of the export, resolved from the header row. the app export
+// starts with 交易时间, the older 交易记录明细查询 export starts with 交易号
+struct Header {
+    time: usize,
+    counterparty: usize,
+    description: usize,
+    direction: usize,
+    amount: usize,
+    status: usize,
+    transaction_id: usize,
+    method: Option<usize>,
+    remark: Option<usize>,
+}
+
+impl Header {
+    fn is_header(record: &csv::StringRecord) -> bool {
+        record
+            .get(0)
+            .is_some_and(|s| s.contains("交易时间") || s.contains("交易号"))
+    }
+
+    fn resolve(record: &csv::StringRecord) -> DynResult<Header> {
+        let find_optional = |names: &[&str]| find_column(record, names);
+        let find = |names: &[&str]| require_column(record, names, "支付宝账单");
+        Ok(Header {
+            time: find(&["交易时间", "交易创建时间"])?,
+            counterparty: find(&["交易对方"])?,
+            description: find(&["商品说明", "商品名称"])?,
+            direction: find(&["收/支"])?,
+            amount: find(&["金额", "金额（元）"])?,
+            status: find(&["交易状态"])?,
+            transaction_id: find(&["交易订单号", "交易号"])?,
+            method: find_optional(&["收/付款方式"]),
+            remark: find_optional(&["备注"]),
+        })
+    }
+
+    // largest resolved column index, shorter rows are not transactions
+    fn last_index(&self) -> usize {
+        [
+            self.time,
+            self.counterparty,
+            self.description,
+            self.direction,
+            self.amount,
+            self.status,
+            self.transaction_id,
+        ]
+        .into_iter()
+        .chain(self.method)
+        .chain(self.remark)
+        .max()
+        .unwrap_or(0)
+    }
+}
+
+pub fn read_input_file(
+    input_file: &Path,
+    user: &User,
+    encoding: Option<&'static Encoding>,
+) -> DynResult<Vec<OutputRecord>> {
+    // the web export is GBK, the app export is utf-8
+    let decoder = open_input(input_file, encoding)?;
 
-    // find the first normal line in csv records
     let mut rdr = ReaderBuilder::new()
-        .has_headers(true)
+        .has_headers(false)
         .flexible(true)
         .trim(csv::Trim::All)
         .from_reader(decoder);
 
     let mut records = Vec::new();
-    let mut headers_found = false;
+    let mut header: Option<Header> = None;
+    let mut preamble = Preamble::default();
+    let mut tally = Tally::default();
 
-    // find the first normal line in csv records
     for result in rdr.records() {
         let record = result?;
 
-        if !headers_found {
-            if record.get(0).map_or(false, |s| s.contains("交易时间")) {
-                headers_found = true;
-                continue;
-            } else {
+        // find the first normal line in csv records
+        let header = match &header {
+            Some(header) => header,
+            None => {
+                if Header::is_header(&record) {
+                    header = Some(Header::resolve(&record)?);
+                } else {
+                    preamble.parse_line(&record.iter().collect::<Vec<_>>().join(","));
+                }
                 continue;
             }
+        };
+        // the older export ends with the summary block, e.g. "已支出:1笔,25.50元"
+        if record.len() <= header.last_index() || record.get(header.time).unwrap_or("").is_empty() {
+            preamble.parse_line(&record.iter().collect::<Vec<_>>().join(","));
+            continue;
         }
 
         // get items
         let source = "支付宝";
-        let transaction_time = record.get(0).unwrap_or("").to_string();
-        let counterparty = record.get(2).unwrap_or("").to_string();
-        let mut transaction_type = record.get(5).unwrap_or("").to_string();
-        let description = record.get(4).unwrap_or("").to_string();
-        let amount = record.get(6).unwrap().parse::<f32>().map_err(|e| {
-            format!(
-                "{} {}: 不支持的金额输入格式: {}",
-                transaction_time, source, e
-            )
-        })?;
+        let get = |index: usize| record.get(index).unwrap_or("");
+        let transaction_time = get(header.time).to_string();
+        let counterparty = get(header.counterparty).to_string();
+        let mut transaction_type = match get(header.direction) {
+            // the older export leaves the direction of 不计收支 items empty
+            "" => "不计收支".to_string(),
+            direction => direction.to_string(),
+        };
+        let description = get(header.description).to_string();
+        let amount_str = get(header.amount);
+        let amount = amount_str
+            .chars()
+            .filter(|c| c.is_ascii_digit() || *c == '.')
+            .collect::<String>()
+            .parse::<f32>()
+            .map_err(|e| {
+                format!(
+                    "{} {}: 不支持的金额输入格式: {}",
+                    transaction_time, source, e
+                )
+            })?;
+        tally.add(&transaction_type, amount);
+        // amounts are CNY unless marked with another currency
+        let currency = detect_currency(amount_str).unwrap_or("CNY").to_string();
         // used for income/outcome account and the source account of transfer
-        let mut account_from = record.get(7).unwrap_or("").to_string();
+        // the older export has no payment method
+        let mut account_from = header.method.map_or("未知", get).to_string();
         // only used for transfer item, as the target account
         let mut account_to = String::from(""); // 只有在转账时使用，作为转入账户
-        let status = record.get(8).unwrap_or("").to_string();
-        let remark = record.get(11).unwrap_or("").to_string();
+        let status = get(header.status).to_string();
+        let transaction_id = get(header.transaction_id).to_string();
+        let remark = header.remark.map_or("", get).to_string();
 
         // handle special items
-        if transaction_type == "不计收支" {
-            if description.contains("余额宝") && description.contains("收益发放") {
-                transaction_type = "收入".to_string();
-            } else if description.contains("余额宝-自动转入") {
-                transaction_type = "转账".to_string();
-                account_to = "余额宝".to_string();
-                account_from = "支付宝零钱".to_string();
+        let mut category_fixed = None;
+        if description.contains("花呗") && description.contains("还款") {
+            // repay 花呗 from the paying account
+            transaction_type = "转账".to_string();
+            if account_from.is_empty() || account_from == "未知" || account_from.contains("花呗")
+            {
+                account_from = "账户余额".to_string();
             }
-            if account_from.contains("亲情卡") {
+            account_to = "花呗".to_string();
+        } else if description.contains("分期")
+            && (description.contains("服务费") || description.contains("手续费"))
+        {
+            transaction_type = "支出".to_string();
+            category_fixed = Some(("金融".to_string(), "分期手续费".to_string()));
+        } else if transaction_type == "不计收支" {
+            if let Some((flow_type, flow_from, flow_to)) = yuebao_flow(&description, &account_from)
+            {
+                transaction_type = flow_type.to_string();
+                account_from = flow_from;
+                account_to = flow_to;
+            } else if account_from.contains("亲情卡") {
                 debug!(
                     "{} {}: 跳过亲情卡交易: {:?}",
                     transaction_time, source, record
@@ -76,12 +175,14 @@ pub fn read_input_file(input_file: &Path, user: &User) -> DynResult<Vec<OutputRe
                     transaction_time, source, record
                 );
                 continue;
+            } else if description != "信用卡还款" {
+                // other items are skipped, repayments are booked below by their status
+                debug!(
+                    "{} {}: 跳过其他不计收支交易: {:?}",
+                    transaction_time, source, record
+                );
+                continue;
             }
-            debug!(
-                "{} {}: 跳过其他不计收支交易: {:?}",
-                transaction_time, source, record
-            );
-            continue;
         }
 
         if status == "已关闭" || status == "交易关闭" {
@@ -94,11 +195,8 @@ pub fn read_input_file(input_file: &Path, user: &User) -> DynResult<Vec<OutputRe
             transaction_type = "退款".to_string();
         } else if status == "还款成功" && description == "信用卡还款" {
             transaction_type = "转账".to_string();
+            // resolved from the counterparty and remark later
             account_to = "未知".to_string();
-            warn!(
-                "{} {}: 需要手动添加还款目标卡: {:?}",
-                transaction_time, source, record
-            );
         }
 
         if amount == 0.0 {
@@ -120,21 +218,40 @@ pub fn read_input_file(input_file: &Path, user: &User) -> DynResult<Vec<OutputRe
             .next()
             .unwrap_or(&account_to)
             .to_string();
+        // 花呗 is a credit account, purchases charged to it are booked on it,
+        // including installments like "花呗分期"
+        if account_from.starts_with("花呗") {
+            account_from = "花呗".to_string();
+        }
         account_from = append_user_postfix(&account_from, user);
         account_to = append_user_postfix(&account_to, user);
 
         // category setting
-        let (category1, category2) =
-            filter_category(&counterparty, &description, &transaction_type, amount);
+        let mut rule_hits = Vec::new();
+        let (category1, category2) = match category_fixed {
+            Some(category) => {
+                rule_hits.push("商品说明:分期手续费".to_string());
+                category
+            }
+            None => category::filter_category(
+                &counterparty,
+                &description,
+                &transaction_type,
+                amount,
+                &mut rule_hits,
+            ),
+        };
 
         // prepare remarks
         let remark = description + ": " + &remark;
 
-        // format date-time
-        let formatted_date = format_date(&transaction_time);
+        // parse date-time
+        let date = parse_date_time(&transaction_time)
+            .map_err(|e| format!("{} {}: {}", transaction_time, source, e))?;
 
         let output_record = OutputRecord {
-            date: formatted_date,
+            date,
+            timezone: china_timezone(),
             r#type: transaction_type,
             amount,
             category1,
@@ -142,134 +259,48 @@ pub fn read_input_file(input_file: &Path, user: &User) -> DynResult<Vec<OutputRe
             account1: account_from,
             account2: account_to,
             remark,
-            currency: "CNY".to_string(),
+            currency,
             tag: String::new(),
             source: String::from(source),
+            user: user.clone(),
+            counterparty,
+            transaction_id,
+            row: record.position().map_or(0, |p| p.line()),
+            raw: record.iter().map(|s| s.to_string()).collect(),
+            rule_hits,
         };
 
         records.push(output_record);
     }
 
-    Ok(records)
-}
-
-// format date-time string
-// input：year-month-day hour:minute:second
-// output：year年month月day日 hour:minute:second
-fn format_date(input: &str) -> String {
-    let parts: Vec<&str> = input.split_whitespace().collect();
-    assert!(parts.len() == 2, "日期时间格式不正确: {}", input);
-
-    let date_part = parts[0];
-    let time_part = parts[1];
-
-    let date_components: Vec<&str> = date_part.split('-').collect();
-    assert!(date_components.len() == 3, "日期格式不正确: {}", date_part);
-
-    let year = date_components[0];
-    let month = date_components[1].to_string();
-    let day = date_components[2].to_string();
+    preamble::verify("支付宝", &preamble, &tally);
 
-    let time_components: Vec<&str> = time_part.split(':').collect();
-    assert!(time_components.len() == 3, "时间格式不正确: {}", time_part);
-
-    let hour = time_components[0].to_string();
-    let minute = time_components[1].to_string();
-    let second = time_components[2].to_string();
-
-    format!(
-        "{}年{}月{}日 {}:{}:{}",
-        year, month, day, hour, minute, second
-    )
+    Ok(records)
 }
 
-fn filter_category(
-    counterparty: &str,
-    remark: &str,
-    transaction_type: &str,
-    amount: f32,
-) -> (String, String) {
-    if transaction_type == "转账" {
-        return ("".to_string(), "".to_string());
+// 余额宝 movements: yields are income of 余额宝, transfers in and out are
+// transfers between 余额宝 and the payment method, the balance or a bank card
+fn yuebao_flow(description: &str, method: &str) -> Option<(&'static str, String, String)> {
+    if !description.contains("余额宝") {
+        return None;
     }
-
-    let mut category1 = "未知".to_string();
-    let mut category2 = "".to_string();
-    match counterparty {
-        "北京一卡通" => {
-            category1 = "交通".to_string();
-            if amount < 2.0 {
-                category2 = "公交".to_string();
-            } else {
-                category2 = "地铁".to_string();
-            }
-        }
-        "饿了么" => {
-            category1 = "餐饮".to_string();
-            category2 = "外卖".to_string();
-        }
-        "兴全基金管理有限公司" => {
-            if transaction_type == "收入" {
-                category1 = "资本".to_string();
-                category2 = "投资收入".to_string();
-            } else if transaction_type == "支出" {
-                category1 = "资本".to_string();
-                category2 = "投资亏损".to_string();
-            }
-        }
-        "中国移动" => {
-            if remark.contains("话费充值") {
-                category1 = "账单".to_string();
-                category2 = "电话费".to_string();
-            }
-        }
-        "蚂蚁森林" => {
-            category1 = "意外收入".to_string();
-        }
-        "Steam" => {
-            category1 = "网络".to_string();
-            category2 = "游戏".to_string();
-        }
-        "众博康健大药房" => {
-            category1 = "医疗".to_string();
-            category2 = "药品".to_string();
-        }
-        "北京永辉超市有限公司" => {
-            category1 = "食材".to_string();
-            category2 = "蔬菜".to_string();
-        }
-        "北京大学口腔医院" => {
-            category1 = "医疗".to_string();
-            category2 = "牙齿".to_string();
-        }
-        "淮南牛肉汤" => {
-            category1 = "餐饮".to_string();
-            category2 = "三餐".to_string();
-        }
-        "汤鲜生浦项中心店" => {
-            category1 = "餐饮".to_string();
-            category2 = "三餐".to_string();
-        }
-        "滴滴出行（北京）网络平台技术有限公司" => {
-            category1 = "交通".to_string();
-            category2 = "打车".to_string();
-        }
-        _ => {}
-    }
-
-    match remark {
-        "电费" => {
-            category1 = "账单".to_string();
-            category2 = "电费".to_string();
-        }
-        "火车票" => {
-            category1 = "交通".to_string();
-            category2 = "火车".to_string();
-        }
-        _ => {}
+    if description.contains("收益发放") {
+        Some(("收入", "余额宝".to_string(), String::new()))
+    } else if description.contains("转出到余额") || description.contains("转出到账户余额")
+    {
+        Some(("转账", "余额宝".to_string(), "账户余额".to_string()))
+    } else if description.contains("转出到银行卡") || description.contains("提现") {
+        // the card is resolved from the remark later
+        Some(("转账", "余额宝".to_string(), "未知".to_string()))
+    } else if description.contains("转入") {
+        let from = match method {
+            "" | "未知" | "余额宝" => "账户余额",
+            method => method,
+        };
+        Some(("转账", from.to_string(), "余额宝".to_string()))
+    } else {
+        None
     }
-
-    (category1, category2)
 }
 
 fn append_user_postfix(account: &str, user: &User) -> String {
@@ -277,7 +308,7 @@ fn append_user_postfix(account: &str, user: &User) -> String {
     if account == "账户余额" {
         account = String::from("支付宝零钱");
     }
-    if !(account == "支付宝零钱" || account == "余额宝") {
+    if !(account == "支付宝零钱" || account == "余额宝" || account == "花呗") {
         return account.to_string();
     }
 
@@ -287,11 +318,17 @@ fn append_user_postfix(account: &str, user: &User) -> String {
     }
 }
 
-pub fn handle_bill(user: &arguments::User, records: &mut Vec<OutputRecord>, input_file: &PathBuf) {
+pub fn handle_bill(
+    user: &arguments::User,
+    records: &mut Vec<OutputRecord>,
+    input_file: &PathBuf,
+    encoding: Option<&'static Encoding>,
+) {
     let input_file = Path::new(input_file);
     info!("处理账单文件: {}", input_file.display());
     println!("处理支付宝账单: {}", input_file.display());
-    let current_records = read_input_file(input_file, user).expect("read input csv file error");
+    let current_records =
+        read_input_file(input_file, user, encoding).expect("read input csv file error");
     println!("处理支付宝账单条目数量: {}", current_records.len());
     records.extend(current_records);
 }